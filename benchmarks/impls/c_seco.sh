@@ -5,8 +5,10 @@ set -eu
 project='c_template'
 cp -a "$(dirname "${0}")/${project}" .
 
+# Options after the dataset are passed to secohash.
 seco_cmd=(
   "$(dirname "${0}")/../../target/debug/secohash"
+  "${@:2}"
 )
 
 if ! "${seco_cmd[@]}" < "${1}" > "${project}/hasher.c"; then
@@ -14,29 +16,23 @@ if ! "${seco_cmd[@]}" < "${1}" > "${project}/hasher.c"; then
 fi
 
 hash_table_size="$(
-  tr '\n' '\r' < "${project}/hasher.c" | \
-  grep -Eo 'const struct entry entries\[\] = [^;]+;' | \
-  tr '\r' '\n' | \
-  wc -l)"
-(( hash_table_size -= 2 ))
+  grep -Eo '(entries|values)\[[0-9]+\] =' "${project}/hasher.c" | \
+  grep -Eo '[0-9]+\]' | \
+  tr -d ']' | \
+  head -n 1)"
 echo "${hash_table_size}" > "hash_table_size"
 
-uint8_t_count=$(
-  grep -Eo 'static const uint8_t t[0-9]+\[\] = [^;]+;' "${project}/hasher.c" | \
-  tr -dc ',;' | \
-  wc -c
-)
-uint16_t_count=$(
-  grep -Eo 'static const uint16_t t[0-9]+\[\] = [^;]+;' "${project}/hasher.c" | \
-  tr -dc ',;' | \
-  wc -c
-)
-uint32_t_count=$(
-  grep -Eo 'static const uint32_t t[0-9]+\[\] = [^;]+;' "${project}/hasher.c" | \
-  tr -dc ',;' | \
-  wc -c
-)
-echo "$(( uint8_t_count + 2 * uint16_t_count + 4 * uint32_t_count ))" > "data_bytes"
+data_bytes=0
+for bits in 8 16 32; do
+  for len in $(
+    grep -Eo "static const uint${bits}_t t[0-9]+\[[0-9]+\]" "${project}/hasher.c" | \
+    grep -Eo '[0-9]+\]' | \
+    tr -d ']'
+  ); do
+    data_bytes=$(( data_bytes + len * bits / 8 ))
+  done
+done
+echo "${data_bytes}" > "data_bytes"
 
 (cd "${project}" && GCC_FLAGS='-Werror' make)
 
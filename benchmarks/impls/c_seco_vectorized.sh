@@ -0,0 +1,5 @@
+#!/bin/bash
+
+set -eu
+
+bash "$(dirname "${0}")/c_seco.sh" "${1}" --str-sum=vectorized
@@ -0,0 +1,50 @@
+#!/bin/bash
+
+set -eu
+
+project='cpp_template'
+cp -a "$(dirname "${0}")/${project}" .
+
+seco_cmd=(
+  "$(dirname "${0}")/../../target/debug/secohash"
+  --target=cpp
+  --namespace=seco
+)
+
+if ! "${seco_cmd[@]}" < "${1}" > "${project}/hasher.hpp"; then
+  exit 0
+fi
+
+hash_table_size="$(
+  grep -Eo 'keys\[[0-9]+\] =' "${project}/hasher.hpp" | \
+  grep -Eo '[0-9]+\]' | \
+  tr -d ']' | \
+  head -n 1)"
+echo "${hash_table_size}" > "hash_table_size"
+
+data_bytes=0
+for bits in 8 16 32; do
+  for len in $(
+    grep -Eo "constexpr std::uint${bits}_t t[0-9]+\[[0-9]+\]" "${project}/hasher.hpp" | \
+    grep -Eo '[0-9]+\]' | \
+    tr -d ']'
+  ); do
+    data_bytes=$(( data_bytes + len * bits / 8 ))
+  done
+done
+echo "${data_bytes}" > "data_bytes"
+
+cat > "${project}/hasher.cpp" << EOF
+#include "hasher.hpp"
+
+void init() {}
+
+uint32_t lookup(std::string_view str) {
+    return seco::lookup(str).value_or(-1);
+}
+EOF
+
+(cd "${project}" && GXX_FLAGS='-std=c++17 -Werror' make)
+
+mv "${project}/run" run
+#rm -r "${project}"
@@ -0,0 +1,31 @@
+#!/bin/bash
+
+# Compares the StrSum lowerings on one dataset by timing hash() alone. Any
+# arguments after the dataset are passed to gcc, e.g. -march=native.
+#
+#   ./str_sum.sh [dataset] [gcc flags...]
+#
+# Recorded results are in README.md.
+
+set -eu
+
+dataset="$(realpath "${1:-"$(dirname "${0}")/datasets/hex_64_16k.txt"}")"
+gcc_flags=("${@:2}")
+
+cd "$(dirname "${0}")"
+
+(cd .. && cargo build --release)
+
+bin='bin/str_sum'
+rm -rf "${bin}"
+mkdir -p "${bin}"
+
+for lowering in unrolled vectorized; do
+  hasher="$(realpath "${bin}")/${lowering}.c"
+  ../target/release/secohash --str-sum="${lowering}" \
+    < "${dataset}" > "${hasher}" 2> "${bin}/${lowering}.log"
+  grep -q 'str_sum' "${hasher}" || echo "warning: the hash of ${dataset} has no sum"
+  gcc -O3 -Wall -Wextra ${gcc_flags[@]+"${gcc_flags[@]}"} \
+    -DHASHER="\"${hasher}\"" utils/hash_bench.c -o "${bin}/${lowering}"
+  echo -e "${lowering}\t$("${bin}/${lowering}" < "${dataset}")"
+done
@@ -0,0 +1,55 @@
+// Times hash() on the first KEYS keys of stdin, which stay in the cache, so
+// that only the hash itself is measured. HASHER names the generated source.
+#include <inttypes.h>
+#include <stdio.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/types.h>
+#include <time.h>
+
+#include HASHER
+
+#define KEYS 512
+#define ROUNDS 2000
+#define REPEATS 7
+
+static double now_ns(void) {
+    struct timespec ts;
+    clock_gettime(CLOCK_MONOTONIC, &ts);
+    return ts.tv_sec * 1e9 + ts.tv_nsec;
+}
+
+int main() {
+    char* keys[KEYS];
+    size_t lens[KEYS];
+    size_t n = 0;
+    char* line = NULL;
+    size_t line_size = 0;
+    ssize_t len;
+    while (n < KEYS && (len = getline(&line, &line_size, stdin)) != -1) {
+        len--;
+        keys[n] = malloc(len + 1);
+        memcpy(keys[n], line, len);
+        keys[n][len] = '\0';
+        lens[n] = len;
+        n++;
+    }
+
+    double best = 0;
+    uint32_t total = 0;
+    for (int repeat = 0; repeat < REPEATS; repeat++) {
+        double start = now_ns();
+        for (int round = 0; round < ROUNDS; round++) {
+            for (size_t i = 0; i < n; i++) {
+                total += hash(keys[i], lens[i]);
+            }
+        }
+        double elapsed = (now_ns() - start) / ((double) ROUNDS * n);
+        if (repeat == 0 || elapsed < best) {
+            best = elapsed;
+        }
+    }
+    // The total keeps the calls from being optimized out.
+    printf("%.1f ns per hash (%" PRIu32 ")\n", best, total);
+}
@@ -1,63 +1,398 @@
 mod c_expr;
 mod c_str_formatter;
+mod cpp;
+mod go;
+mod js;
 mod lines;
+mod lower;
+mod python;
+mod template;
 
 use crate::{
-    ir::{constant_propagation, BinOp, Expr, Instr, Table, Tac, Var},
-    search::Phf,
+    ir::{bit_width, needed_positions, Expr, PackedTables, Tables, Tac},
+    search::{DecisionTree, Phf},
     spec::Spec,
     util::to_u32,
 };
-use c_expr::{CBinOp, CExpr, CExprBuilder};
+use c_expr::{CExpr, CExprBuilder};
 use c_str_formatter::CStrFormatter;
+pub use cpp::{CppBackend, CppBackendConfig};
+pub use go::{GoBackend, GoBackendConfig};
+pub use js::{JsBackend, JsBackendConfig};
 use lines::Lines;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use lower::{lower, reads_key, str_sum_bounded_terms, str_sum_term, Helpers, Operands};
+pub use python::PythonBackend;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+};
+pub use template::{builtin_template, Template, TemplateBackend};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyLayout {
+    // Inline if the keys are short enough, otherwise entries.
+    Auto,
+    // An array of structs holding a pointer, length and value for each slot.
+    Entries,
+    // One string containing every key, with parallel arrays of offsets,
+    // lengths and values.
+    Pool,
+    // The keys themselves stored in each slot. If the keys all have the same
+    // length, they are packed into integers and compared with word loads.
+    Inline,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyAccess {
+    // The key is passed as a pointer to contiguous symbols.
+    Contiguous,
+    // Each symbol is read through a callback, for keys that are split across
+    // buffers.
+    Callback,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputStyle {
+    // One translation unit exporting hash() and lookup().
+    Source,
+    // A header declaring hash() and lookup(), and a source file defining them.
+    Split,
+    // A single header defining everything as static inline.
+    Header,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StrSumLowering {
+    // Unrolled by hand, with vectorization disabled.
+    Unrolled,
+    // Lanes of partial sums that compilers turn into vector adds. Keys read
+    // through a callback fall back to Unrolled.
+    Vectorized,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CStandard {
+    // Declarations before statements, no inline and no // comments.
+    C89,
+    C99,
+}
+
+// Unsigned integer types to use where stdint.h is unavailable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IntTypes {
+    pub u8: String,
+    pub u16: String,
+    pub u32: String,
+    pub u64: String,
+}
+
+impl Default for IntTypes {
+    fn default() -> Self {
+        IntTypes {
+            u8: "unsigned char".into(),
+            u16: "unsigned short".into(),
+            u32: "unsigned int".into(),
+            u64: "unsigned long long".into(),
+        }
+    }
+}
+
+// The number of partial sums in StrSumLowering::Vectorized, enough to fill a
+// 512-bit vector of 32-bit integers.
+const VECTOR_LANES: u32 = 16;
+
+// Keys of at most this many bytes are stored inline by KeyLayout::Auto.
+const MAX_INLINE_KEY_LEN: usize = 16;
+
+pub struct CBackendConfig {
+    pub key_layout: KeyLayout,
+    pub key_access: KeyAccess,
+    pub output_style: OutputStyle,
+    // Prepended to every symbol, so that several tables can be linked into
+    // one program.
+    pub prefix: String,
+    // The name under which the source file includes the header.
+    pub header_name: String,
+    pub standard: CStandard,
+    pub str_sum_lowering: StrSumLowering,
+    // None to use the types from stdint.h.
+    pub int_types: Option<IntTypes>,
+}
+
+impl Default for CBackendConfig {
+    fn default() -> Self {
+        CBackendConfig {
+            key_layout: KeyLayout::Entries,
+            key_access: KeyAccess::Contiguous,
+            output_style: OutputStyle::Source,
+            prefix: String::new(),
+            header_name: "hasher.h".into(),
+            standard: CStandard::C99,
+            str_sum_lowering: StrSumLowering::Unrolled,
+            int_types: None,
+        }
+    }
+}
+
+pub struct OutputFile {
+    pub extension: String,
+    pub contents: String,
+}
+
+// The name of the array holding each table, and the index of its first element.
+type TableRefs = Vec<(String, u32)>;
+
+// Tables with the same element type share one backing array. Returns the
+// element bits, name and data of each array, and the array name and base of
+// each table.
+fn pack_tables(tables: &Tables) -> (Vec<(u32, String, Vec<u32>)>, TableRefs) {
+    let mut tables_by_bits: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+    for (i, table) in tables.tables().iter().enumerate() {
+        let bits = match bit_width(table.iter().copied().max().unwrap()) {
+            0..=8 => 8,
+            9..=16 => 16,
+            _ => 32,
+        };
+        tables_by_bits.entry(bits).or_default().push(i);
+    }
+    let mut arrays = Vec::new();
+    let mut table_refs = vec![(String::new(), 0); tables.tables().len()];
+    for (bits, members) in tables_by_bits {
+        let member_tables: Vec<&[u32]> = members
+            .iter()
+            .map(|&i| tables.tables()[i].as_slice())
+            .collect();
+        let packed = PackedTables::new(&member_tables);
+
+        let name = format!("t{bits}");
+        for (&i, &base) in members.iter().zip(&packed.bases) {
+            table_refs[i] = (name.clone(), to_u32(base));
+        }
+        arrays.push((bits, name, packed.data));
+    }
+    (arrays, table_refs)
+}
 
 pub struct CBackend {
     spec: Spec,
     phf: Phf,
+    config: CBackendConfig,
+    // The widths passed to uint(), so that only those types are declared.
+    used_uint_bits: RefCell<BTreeSet<u32>>,
+}
+
+impl Operands for CBackend {
+    // Symbols are read as unsigned char, as in the IR, since char is signed on
+    // most targets. Narrow values promote to int, which overflows when the
+    // mixer shifts them far, so they are widened to the unsigned 32 bits of
+    // the IR first.
+    fn str_get(&self, i: CExpr) -> CExpr {
+        let x = CExprBuilder();
+        let symbol = match self.config.key_access {
+            KeyAccess::Contiguous => x.cast("unsigned char", x.index("key", i)),
+            KeyAccess::Callback => x.call("key_at".into(), vec![x.var("ctx"), i]),
+        };
+        x.cast(self.uint(32), symbol)
+    }
+
+    fn str_len(&self) -> CExpr {
+        let x = CExprBuilder();
+        x.cast(self.uint(32), x.var("len"))
+    }
+
+    fn str_sum(&self, mask: u32) -> CExpr {
+        CExprBuilder().call(self.sym(&format!("str_sum_{mask}")), self.key_args())
+    }
+
+    fn str_sum_bounded(&self, mask: u32, head: u32, tail: u32) -> CExpr {
+        let name = self.sym(&format!("str_sum_{mask}_{head}_{tail}"));
+        CExprBuilder().call(name, self.key_args())
+    }
+
+    fn str_hash(&self, seed: u32) -> CExpr {
+        CExprBuilder().call(self.sym(&format!("str_hash_{seed}")), self.key_args())
+    }
+
+    // Elements of 8 and 16 bits promote to int like symbols do.
+    fn table_get(&self, table: &str, i: CExpr) -> CExpr {
+        let x = CExprBuilder();
+        x.cast(self.uint(32), x.index(table, i))
+    }
 }
 
 impl CBackend {
-    pub fn new(spec: Spec, phf: Phf) -> CBackend {
-        CBackend { spec, phf }
+    pub fn new(spec: Spec, phf: Phf, config: CBackendConfig) -> CBackend {
+        CBackend {
+            spec,
+            phf,
+            config,
+            used_uint_bits: RefCell::new(BTreeSet::new()),
+        }
+    }
+
+    fn sym(&self, name: &str) -> String {
+        format!("{}{name}", self.config.prefix)
     }
 
-    fn expr_to_c_expr(&self, expr: &Expr) -> CExpr {
+    // The unsigned integer type with the given number of bits.
+    fn uint(&self, bits: u32) -> String {
+        self.used_uint_bits.borrow_mut().insert(bits);
+        match self.config.int_types {
+            None => format!("uint{bits}_t"),
+            // Short names like u32 often clash with the caller's own typedefs.
+            Some(_) if self.config.prefix.is_empty() => format!("seco_u{bits}"),
+            Some(_) => self.sym(&format!("u{bits}")),
+        }
+    }
+
+    fn inline(&self) -> &'static str {
+        match self.config.standard {
+            CStandard::C89 => "SECO_INLINE",
+            CStandard::C99 => "inline",
+        }
+    }
+
+    fn comment(&self, text: &str) -> String {
+        match self.config.standard {
+            CStandard::C89 => format!("/* {text} */"),
+            CStandard::C99 => format!("// {text}"),
+        }
+    }
+
+    // The storage class of hash() and lookup().
+    fn linkage(&self) -> String {
+        match self.config.output_style {
+            OutputStyle::Source | OutputStyle::Split => String::new(),
+            OutputStyle::Header => format!("static {} ", self.inline()),
+        }
+    }
+
+    fn key_args(&self) -> Vec<CExpr> {
         let x = CExprBuilder();
-        match *expr {
-            Expr::Var(Var(i)) => x.var(format!("x{i}")),
-            Expr::Reg(_) => panic!(),
-            Expr::Imm(n) => x.imm(n),
-            Expr::StrGet(ref i) => x.index("key", self.expr_to_c_expr(i.as_ref())),
-            Expr::StrLen => x.cast("uint32_t", x.var("len")),
-            Expr::StrSum(mask) => {
-                x.call(format!("str_sum_{mask}"), vec![x.var("key"), x.var("len")])
-            }
-            Expr::TableGet(Table(t), ref i) => {
-                x.index(format!("t{t}"), self.expr_to_c_expr(i.as_ref()))
-            }
-            Expr::TableIndexMask(t) => x.imm(to_u32(self.phf.tables[t].len() - 1)),
-            Expr::HashMask => x.imm(to_u32(self.phf.key_table.len() - 1)),
-            Expr::BinOp(op, ref a, ref b) => {
-                let op = match op {
-                    BinOp::Add => CBinOp::Add,
-                    BinOp::Sub => CBinOp::Sub,
-                    BinOp::And => CBinOp::And,
-                    BinOp::Shll => CBinOp::Shl,
-                    BinOp::Shrl => CBinOp::Shr,
-                };
-                let a = self.expr_to_c_expr(a.as_ref());
-                let b = self.expr_to_c_expr(b.as_ref());
-                x.bin_op(op, a, b)
-            }
+        match self.config.key_access {
+            KeyAccess::Contiguous => vec![x.var("key"), x.var("len")],
+            KeyAccess::Callback => vec![x.var("key_at"), x.var("ctx"), x.var("len")],
         }
     }
 
-    fn compile_str_sum(lines: &mut Lines, mask: u32) {
-        lines.push("__attribute__((optimize(\"no-tree-vectorize\")))");
+    fn key_params(&self) -> String {
+        match self.config.key_access {
+            KeyAccess::Contiguous => "const char* key, size_t len".into(),
+            KeyAccess::Callback => format!(
+                "{} key_at, const void* ctx, size_t len",
+                self.sym("key_at_fn")
+            ),
+        }
+    }
+
+    // Each symbol is guarded by a length check instead of looping, so that the
+    // time taken does not depend on the length.
+    fn compile_str_sum_bounded(&self, lines: &mut Lines, mask: u32, head: u32, tail: u32) {
         lines.push(&format!(
-            "uint32_t str_sum_{mask}(const char* key, size_t len) {{"
+            "static {} {} {}({}) {{",
+            self.inline(),
+            self.uint(32),
+            self.sym(&format!("str_sum_{mask}_{head}_{tail}")),
+            self.key_params()
+        ));
+        let body_indent = lines.indent();
+        let x = CExprBuilder();
+        lines.push(&format!("{} sum = 0;", self.uint(32)));
+        for (len_above, term) in str_sum_bounded_terms(self, x.var("len"), mask, head, tail) {
+            lines.push(&format!("sum += len > {len_above} ? {term} : 0;"));
+        }
+        lines.push("return sum;");
+        lines.dedent(body_indent);
+        lines.push("}");
+    }
+
+    fn compile_str_hash(&self, lines: &mut Lines, seed: u32) {
+        let x = CExprBuilder();
+        let u32_type = self.uint(32);
+        lines.push(&format!(
+            "static {} {u32_type} {}({}) {{",
+            self.inline(),
+            self.sym(&format!("str_hash_{seed}")),
+            self.key_params()
+        ));
+        let body_indent = lines.indent();
+        lines.extend(&[
+            &format!("{u32_type} h = {seed};"),
+            "size_t i;",
+            "for (i = 0; i < len; i++) {",
+            &format!("\th += {};", self.str_get(x.var("i"))),
+            "\th += h << 10;",
+            "\th ^= h >> 6;",
+            "}",
+            "h += h << 3;",
+            "h ^= h >> 11;",
+            "h += h << 15;",
+            "return h;",
+        ]);
+        lines.dedent(body_indent);
+        lines.push("}");
+    }
+
+    fn vectorize_str_sum(&self) -> bool {
+        self.config.str_sum_lowering == StrSumLowering::Vectorized
+            && self.config.key_access == KeyAccess::Contiguous
+    }
+
+    // The shift of each symbol repeats every shift_stride positions, so when
+    // the number of lanes is a multiple of it, each lane can be shifted once at
+    // the end instead of for every symbol.
+    fn compile_str_sum_vectorized(&self, lines: &mut Lines, mask: u32) {
+        let x = CExprBuilder();
+        let u32_type = self.uint(32);
+        let lanes = VECTOR_LANES.max((mask + 1).next_power_of_two());
+        lines.push(&format!(
+            "static {} {u32_type} {}({}) {{",
+            self.inline(),
+            self.sym(&format!("str_sum_{mask}")),
+            self.key_params()
+        ));
+        let body_indent = lines.indent();
+        lines.extend(&[
+            &format!("{u32_type} lanes[{lanes}] = {{0}};"),
+            &format!("{u32_type} sum = 0;"),
+            "size_t i = 0;",
+            "size_t j;",
+            &format!("for (; i + {lanes} <= len; i += {lanes}) {{"),
+            &format!("\tfor (j = 0; j < {lanes}; j++) {{"),
+            &format!(
+                "\t\tlanes[j] += {};",
+                self.str_get(x.add(x.var("i"), x.var("j")))
+            ),
+            "\t}",
+            "}",
+            &format!("for (j = 0; j < {lanes}; j++) {{"),
+            &format!(
+                "\tsum += {};",
+                x.shl(x.index("lanes", x.var("j")), x.and(x.var("j"), x.imm(mask)))
+                    .cleaned()
+            ),
+            "}",
+            "for (; i < len; i++) {",
+            &format!("\tsum += {};", str_sum_term(self, mask, x.var("i"))),
+            "}",
+            "return sum;",
+        ]);
+        lines.dedent(body_indent);
+        lines.push("}");
+    }
+
+    fn compile_str_sum(&self, lines: &mut Lines, mask: u32) {
+        if self.vectorize_str_sum() {
+            self.compile_str_sum_vectorized(lines, mask);
+            return;
+        }
+        lines.push("SECO_NO_VECTORIZE");
+        lines.push(&format!(
+            "static {} {} {}({}) {{",
+            self.inline(),
+            self.uint(32),
+            self.sym(&format!("str_sum_{mask}")),
+            self.key_params()
         ));
         let body_indent = lines.indent();
 
@@ -71,11 +406,15 @@ impl CBackend {
         let unroll = 4;
 
         let unrolled = unroll > 1;
+        let u32_type = self.uint(32);
         if unrolled {
             for lane in 0..unroll {
-                lines.push(&format!("uint32_t sum_{lane} = 0;"));
+                lines.push(&format!("{u32_type} sum_{lane} = 0;"));
             }
-            lines.push("size_t i = 0;");
+        }
+        lines.push(&format!("{u32_type} sum = 0;"));
+        lines.push("size_t i = 0;");
+        if unrolled {
             lines.push(&format!(
                 "for (; i + {} < len; i += {unroll}) {{",
                 unroll - 1
@@ -85,18 +424,13 @@ impl CBackend {
             let shift_later = unroll >= shift_stride;
 
             for lane in 0..unroll {
-                lines.push(&format!(
-                    "sum_{lane} += {};",
-                    x.shl(
-                        x.index("key", x.add(x.var("i"), x.imm(lane))),
-                        if shift_later {
-                            x.imm(0)
-                        } else {
-                            x.and(x.add(x.var("i"), x.imm(lane)), x.imm(mask))
-                        }
-                    )
-                    .cleaned()
-                ));
+                let i = x.add(x.var("i"), x.imm(lane)).cleaned();
+                let term = if shift_later {
+                    self.str_get(i)
+                } else {
+                    str_sum_term(self, mask, i)
+                };
+                lines.push(&format!("sum_{lane} += {term};"));
             }
 
             lines.dedent(for_indent);
@@ -126,21 +460,12 @@ impl CBackend {
                 )
                 .cleaned();
 
-            lines.push(&format!("uint32_t sum = {};", shifted_sum));
-        } else {
-            lines.push("uint32_t sum = 0;");
-        };
+            lines.push(&format!("sum = {};", shifted_sum));
+        }
 
-        lines.push(&format!(
-            "for ({}; i < len; i++) {{",
-            if unrolled { "" } else { "size_t i = 0" }
-        ));
+        lines.push("for (; i < len; i++) {");
         let for_indent = lines.indent();
-        lines.push(&format!(
-            "sum += {};",
-            x.shl(x.index("key", x.var("i")), x.and(x.var("i"), x.imm(mask)))
-                .cleaned()
-        ));
+        lines.push(&format!("sum += {};", str_sum_term(self, mask, x.var("i"))));
         lines.dedent(for_indent);
         lines.push("}");
         lines.push("return sum;");
@@ -165,137 +490,910 @@ impl CBackend {
         lines.push("};");
     }
 
-    pub fn emit(&self) -> String {
-        let spec = &self.spec;
-        let phf = &self.phf;
-
-        let expr = phf.tac.unflatten_tree(phf.tac.last_reg(), &HashMap::new());
-        let expr = constant_propagation(expr);
-        let mut tac = Tac::new();
-        expr.flatten(&mut tac, &HashMap::new());
-        let (tac, _) = tac.local_value_numbering();
-
-        let key_used = tac.instrs().iter().any(|i| matches!(&i, Instr::StrGet(_)));
-
-        let unused_prefix = "__attribute__((unused)) ";
-        let key_declaration = "const char* key";
-        let len_declaration = "size_t len";
+    fn key_bytes(key: &[u32]) -> Vec<u8> {
+        key.iter().map(|&c| u8::try_from(c).unwrap()).collect()
+    }
 
-        let mut lines = Lines::new(80, 4, true);
+    // The bits of the narrowest type that holds max.
+    fn uint_bits(max: u32) -> u32 {
+        match bit_width(max) {
+            0..=8 => 8,
+            9..=16 => 16,
+            _ => 32,
+        }
+    }
 
+    fn compile_entries(&self, lines: &mut Lines) {
+        let entry = self.sym("entry");
         lines.extend(&[
-            "#include <stddef.h>",
-            "#include <stdint.h>",
-            "#include <string.h>",
-            "",
-            "struct entry {",
-            "\tchar* key;",
-            "\tuint32_t len;",
-            "\tuint32_t value;",
+            &format!("struct {entry} {{"),
+            "\tconst char* key;",
+            &format!("\t{} len;", self.uint(32)),
+            &format!("\t{} value;", self.uint(32)),
             "};",
         ]);
 
         lines.push_empty();
         let str_formatter = CStrFormatter::new();
         let mut entry_structs = Vec::new();
-        for (i, key) in phf.key_table.iter().enumerate() {
-            let bytes = key.iter().map(|&c| u8::try_from(c).unwrap()).collect();
-            let string_literal = str_formatter.format(bytes);
-
+        for (i, key) in self.phf.key_table.iter().enumerate() {
+            let string_literal = str_formatter.format(Self::key_bytes(key));
             let len = key.len();
-            let is_fake_key = (i == 0) ^ (key.is_empty());
-            let ordinal: String = if is_fake_key {
-                "-1".into()
-            } else {
-                spec.keys.iter().position(|k| k == key).unwrap().to_string()
+            let ordinal = match self.phf.ordinals[i] {
+                Some(ordinal) => ordinal.to_string(),
+                None => format!("({}) -1", self.uint(32)),
             };
-
             entry_structs.push(format!("{{{string_literal}, {len}, {ordinal}}}"));
         }
         Self::compile_array(
-            &mut lines,
-            &format!("const struct entry entries[{}]", entry_structs.len()),
+            lines,
+            &format!(
+                "static const struct {entry} {}[{}]",
+                self.sym("entries"),
+                entry_structs.len()
+            ),
             &entry_structs,
         );
+    }
+
+    // All the keys are concatenated into one string, with separate arrays for
+    // the offsets, lengths and values, so that no slot needs a pointer.
+    fn compile_key_pool(&self, lines: &mut Lines) {
+        let key_table = &self.phf.key_table;
+        let keys: Vec<&[u32]> = key_table.iter().map(Vec::as_slice).collect();
+        let pool = PackedTables::new(&keys);
 
-        let mut str_sum_masks = HashSet::new();
-        for instr in tac.instrs() {
-            if let Instr::StrSum(mask) = *instr {
-                str_sum_masks.insert(mask);
+        let str_formatter = CStrFormatter::new();
+        let bytes = Self::key_bytes(&pool.data);
+        lines.push(&format!(
+            "static const char {}[{}] =",
+            self.sym("key_pool"),
+            pool.data.len() + 1
+        ));
+        let pool_indent = lines.indent();
+        let literals = str_formatter.format_split(bytes, lines.text_cols() - 1);
+        for (i, literal) in literals.iter().enumerate() {
+            if i == literals.len() - 1 {
+                lines.push(&format!("{literal};"));
+            } else {
+                lines.push(literal);
             }
         }
-        let mut str_sum_masks: Vec<u32> = str_sum_masks.into_iter().collect();
-        str_sum_masks.sort();
-        for mask in str_sum_masks {
-            lines.push_empty();
-            Self::compile_str_sum(&mut lines, mask);
+        lines.dedent(pool_indent);
+
+        let offsets: Vec<u32> = pool.bases.iter().map(|&base| to_u32(base)).collect();
+        let lens: Vec<u32> = key_table.iter().map(|key| to_u32(key.len())).collect();
+        let values: Vec<u32> = (0..key_table.len())
+            .map(|i| self.phf.ordinals[i].map_or(0, to_u32))
+            .collect();
+        self.compile_column(lines, "key_offsets", &offsets);
+        self.compile_column(lines, "key_lens", &lens);
+        self.compile_column(lines, "values", &values);
+    }
+
+    fn key_layout(&self) -> KeyLayout {
+        match self.config.key_layout {
+            KeyLayout::Auto if self.spec.max_interpreted_key_len <= MAX_INLINE_KEY_LEN => {
+                KeyLayout::Inline
+            }
+            KeyLayout::Auto => KeyLayout::Entries,
+            layout => layout,
         }
+    }
 
-        lines.push_empty();
+    // The length of every key, if they all have the same length and are short
+    // enough to be packed into at most two words. Packing needs contiguous keys.
+    fn fixed_key_len(&self) -> Option<usize> {
+        let spec = &self.spec;
+        let fixed = spec.min_interpreted_key_len == spec.max_interpreted_key_len
+            && spec.max_interpreted_key_len <= MAX_INLINE_KEY_LEN
+            && self.config.key_access == KeyAccess::Contiguous
+            && spec.keys.iter().all(|key| !key.is_empty());
+        fixed.then_some(spec.max_interpreted_key_len)
+    }
+
+    // The (offset, length) of each word that a fixed-length key is packed into.
+    fn key_words(key_len: usize) -> Vec<(usize, usize)> {
+        if key_len <= 8 {
+            vec![(0, key_len)]
+        } else {
+            vec![(0, 8), (8, key_len - 8)]
+        }
+    }
+
+    fn word_bits(word_len: usize) -> u32 {
+        match word_len {
+            1 => 8,
+            2 => 16,
+            3..=4 => 32,
+            _ => 64,
+        }
+    }
+
+    // Assemble a little-endian word from bytes. Compilers recognize this
+    // pattern and emit a single load where possible.
+    fn compile_pack(&self, lines: &mut Lines, word_len: usize) {
+        let word_type = self.uint(Self::word_bits(word_len));
         lines.push(&format!(
-            "uint32_t hash({}{key_declaration}, {len_declaration}) {{",
-            if key_used { "" } else { unused_prefix },
+            "static {} {word_type} {}(const char* p) {{",
+            self.inline(),
+            self.sym(&format!("pack_{word_len}"))
         ));
-        let hash_indent = lines.indent();
+        lines.push("\tconst unsigned char* u = (const unsigned char*) p;");
+        let bytes: Vec<String> = (0..word_len)
+            .map(|i| match i {
+                0 => format!("({word_type}) u[0]"),
+                _ => format!("({word_type}) u[{i}] << {}", 8 * i),
+            })
+            .collect();
+        lines.push(&format!("\treturn {};", bytes.join(" | ")));
+        lines.push("}");
+    }
 
-        for (i, table) in phf.tables.tables().iter().enumerate() {
-            let max = table.iter().copied().max().unwrap();
-            let mut table_type = "uint32_t";
-            if max <= u16::MAX.into() {
-                table_type = "uint16_t";
-            }
-            if max <= u8::MAX.into() {
-                table_type = "uint8_t";
+    // The functions that pack fixed-length keys, if the keys are inline.
+    fn compile_packs(&self, lines: &mut Lines) {
+        let (KeyLayout::Inline, Some(key_len), None) =
+            (self.key_layout(), self.fixed_key_len(), self.tree())
+        else {
+            return;
+        };
+        let mut word_lens: Vec<usize> = Self::key_words(key_len)
+            .iter()
+            .map(|&(_, len)| len)
+            .collect();
+        word_lens.dedup();
+        for word_len in word_lens {
+            lines.push_empty();
+            self.compile_pack(lines, word_len);
+        }
+    }
+
+    fn compile_keys(&self, lines: &mut Lines) {
+        match self.key_layout() {
+            KeyLayout::Auto => unreachable!(),
+            KeyLayout::Entries => self.compile_entries(lines),
+            KeyLayout::Pool => self.compile_key_pool(lines),
+            KeyLayout::Inline => self.compile_inline_keys(lines),
+        }
+    }
+
+    fn compile_inline_keys(&self, lines: &mut Lines) {
+        let key_table = &self.phf.key_table;
+        let values: Vec<u32> = (0..key_table.len())
+            .map(|i| self.phf.ordinals[i].map_or(0, to_u32))
+            .collect();
+
+        if let Some(key_len) = self.fixed_key_len() {
+            let words = Self::key_words(key_len);
+            // Unused slots hold a copy of a real key that hashes elsewhere, so
+            // they can never match.
+            let fake_key = &key_table[0];
+            for (w, &(offset, word_len)) in words.iter().enumerate() {
+                let elements: Vec<String> = key_table
+                    .iter()
+                    .map(|key| {
+                        let key = if key.is_empty() { fake_key } else { key };
+                        let word = key[offset..offset + word_len]
+                            .iter()
+                            .rev()
+                            .fold(0u64, |word, &c| (word << 8) | u64::from(c));
+                        format!("{word:#x}")
+                    })
+                    .collect();
+                Self::compile_array(
+                    lines,
+                    &format!(
+                        "static const {} {}[{}]",
+                        self.uint(Self::word_bits(word_len)),
+                        self.sym(&format!("key_words_{w}")),
+                        key_table.len()
+                    ),
+                    &elements,
+                );
             }
+        } else {
+            let str_formatter = CStrFormatter::new();
+            let elements: Vec<String> = key_table
+                .iter()
+                .map(|key| str_formatter.format(Self::key_bytes(key)))
+                .collect();
+            Self::compile_array(
+                lines,
+                &format!(
+                    "static const char {}[{}][{}]",
+                    self.sym("key_chars"),
+                    key_table.len(),
+                    self.spec.max_interpreted_key_len + 1
+                ),
+                &elements,
+            );
 
-            let table_size = table.len();
+            let lens: Vec<u32> = key_table.iter().map(|key| to_u32(key.len())).collect();
+            self.compile_column(lines, "key_lens", &lens);
+        }
+        self.compile_column(lines, "values", &values);
+    }
+
+    fn compile_column(&self, lines: &mut Lines, name: &str, column: &[u32]) {
+        let column_type = self.uint(Self::uint_bits(column.iter().copied().max().unwrap()));
+        let elements: Vec<String> = column.iter().map(|n| n.to_string()).collect();
+        Self::compile_array(
+            lines,
+            &format!(
+                "static const {column_type} {}[{}]",
+                self.sym(name),
+                column.len()
+            ),
+            &elements,
+        );
+    }
 
-            let declaration = format!("static const {table_type} t{i}[{table_size}]");
-            let elements = table.iter().map(|x| x.to_string()).collect::<Vec<_>>();
-            Self::compile_array(&mut lines, &declaration, &elements)
+    // Document which positions hash() reads, so that callers can gather just
+    // those symbols. Lengths that read the same positions are grouped.
+    fn compile_needed_positions(&self, lines: &mut Lines, tac: &Tac, tables: &Tables) {
+        let min = self.spec.min_interpreted_key_len;
+        let max = self.spec.max_interpreted_key_len;
+        // None stands for every position, which differs between lengths.
+        let mut groups: Vec<(usize, usize, Option<Vec<usize>>)> = Vec::new();
+        for len in min..=max {
+            let Some(positions) = needed_positions(tac, tables, len) else {
+                lines.push(
+                    &self.comment("hash() reads positions that depend on the contents of the key."),
+                );
+                return;
+            };
+            let positions = (positions.len() < len).then(|| Vec::from_iter(positions));
+            match groups.last_mut() {
+                Some((_, end, last)) if *last == positions => *end = len,
+                _ => groups.push((len, len, positions)),
+            }
         }
 
-        {
-            let min = spec.min_interpreted_key_len;
-            let max = spec.max_interpreted_key_len;
-            let condition = if min == max {
-                format!("len != {min}")
+        lines.push(&self.comment("Positions of the key that hash() reads, by key length:"));
+        for (start, end, positions) in groups {
+            let lens = if start == end {
+                format!("{start}")
             } else {
-                format!("len < {min} || len > {max}")
+                format!("{start}-{end}")
             };
-            lines.push(&format!("if ({condition}) {{"));
-            lines.push("\treturn 0;");
+            let positions = match positions {
+                Some(positions) if positions.is_empty() => "none".to_string(),
+                Some(positions) => positions
+                    .iter()
+                    .map(|i| i.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                None => "all".to_string(),
+            };
+            lines.push(&self.comment(&format!("  {lens}: {positions}")));
+        }
+    }
+
+    // The condition under which the key is too short or too long to be in the
+    // set.
+    fn len_out_of_range(&self) -> String {
+        let min = self.spec.min_interpreted_key_len;
+        let max = self.spec.max_interpreted_key_len;
+        if min == max {
+            format!("len != {min}")
+        } else {
+            format!("len < {min} || len > {max}")
+        }
+    }
+
+    // The decision tree to look keys up with, if any. Keys read through a
+    // callback are compared by the hash table lookup.
+    fn tree(&self) -> Option<&DecisionTree> {
+        self.phf
+            .tree
+            .as_ref()
+            .filter(|_| self.config.key_access == KeyAccess::Contiguous)
+    }
+
+    fn case_label(expr: &Expr, value: u32) -> String {
+        match char::from_u32(value) {
+            Some(c) if *expr != Expr::StrLen && c.is_ascii_graphic() && c != '\'' && c != '\\' => {
+                format!("'{c}'")
+            }
+            _ => value.to_string(),
+        }
+    }
+
+    // key_len is the length of the key, if an enclosing switch decided it.
+    fn compile_tree(&self, lines: &mut Lines, tree: &DecisionTree, key_len: Option<usize>) {
+        match *tree {
+            DecisionTree::Leaf(ref key) => {
+                // The placeholder key of a set with only the empty key.
+                let Some(ordinal) = self.spec.keys.iter().position(|k| k == key) else {
+                    lines.push("return -1;");
+                    return;
+                };
+                let literal = CStrFormatter::new().format(Self::key_bytes(key));
+                let mut condition = format!("memcmp(key, {literal}, len) == 0");
+                if key_len != Some(key.len()) {
+                    condition = format!("len == {} && {condition}", key.len());
+                }
+                lines.push(&format!(
+                    "return {condition} ? {ordinal} : ({}) -1;",
+                    self.uint(32)
+                ));
+            }
+            DecisionTree::Switch(ref expr, ref arms) => {
+                let value = lower(self, &self.phf, &[], expr);
+                lines.push(&format!("switch ({value}) {{"));
+                for (arm_value, arm) in arms {
+                    lines.push(&format!("case {}:", Self::case_label(expr, *arm_value)));
+                    let arm_indent = lines.indent();
+                    let arm_key_len = match *expr {
+                        Expr::StrLen => Some(*arm_value as usize),
+                        _ => key_len,
+                    };
+                    self.compile_tree(lines, arm, arm_key_len);
+                    // Leaves always return, but a nested switch may not match.
+                    if let DecisionTree::Switch(..) = arm {
+                        lines.push("break;");
+                    }
+                    lines.dedent(arm_indent);
+                }
+                lines.push("}");
+            }
+        }
+    }
+
+    // Branches on a few symbols down to the only key that the key can be, and
+    // compares against that, without any table.
+    fn compile_tree_lookup(&self, lines: &mut Lines, tree: &DecisionTree) {
+        if let Some(ordinal) = self.spec.keys.iter().position(Vec::is_empty) {
+            lines.extend(&["if (len == 0) {", &format!("\treturn {ordinal};"), "}"]);
+        }
+        // Symbols are only branched on where hash() would read them, so the
+        // length is checked first unless the tree branches on it.
+        if !matches!(*tree, DecisionTree::Switch(Expr::StrLen, _)) {
+            lines.extend(&[
+                &format!("if ({}) {{", self.len_out_of_range()),
+                "\treturn -1;",
+                "}",
+            ]);
+        }
+        self.compile_tree(lines, tree, None);
+        if let DecisionTree::Switch(..) = *tree {
+            lines.push("return -1;");
+        }
+    }
+
+    fn compile_lookup(&self, lines: &mut Lines) {
+        let key_layout = self.key_layout();
+        lines.push(&format!(
+            "{}{} {}({}) {{",
+            self.linkage(),
+            self.uint(32),
+            self.sym("lookup"),
+            self.key_params()
+        ));
+        let hash_call = format!(
+            "{}({})",
+            self.sym("hash"),
+            self.key_args()
+                .into_iter()
+                .map(|arg| arg.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let lookup_indent = lines.indent();
+
+        if let Some(tree) = self.tree() {
+            self.compile_tree_lookup(lines, tree);
+            lines.dedent(lookup_indent);
             lines.push("}");
+            return;
+        }
+
+        // A header may be included without calling lookup(), so keep the keys
+        // local to it rather than warning about unused statics.
+        if self.config.output_style == OutputStyle::Header {
+            self.compile_keys(lines);
+            lines.push_empty();
+        }
+
+        // Every declaration comes first, as C89 requires. hash() checks the
+        // length itself, so it is safe to call before the checks here.
+        lines.push(&format!("{} i = {hash_call};", self.uint(32)));
+        if let (KeyLayout::Inline, Some(key_len)) = (key_layout, self.fixed_key_len()) {
+            lines.push(&format!("if (len != {key_len}) {{"));
+            lines.push("\treturn -1;");
+            lines.push("}");
+            let comparisons: Vec<String> = Self::key_words(key_len)
+                .into_iter()
+                .enumerate()
+                .map(|(w, (offset, word_len))| {
+                    let ptr = if offset == 0 {
+                        "key".to_string()
+                    } else {
+                        format!("key + {offset}")
+                    };
+                    format!(
+                        "({}({ptr}) == {}[i])",
+                        self.sym(&format!("pack_{word_len}")),
+                        self.sym(&format!("key_words_{w}"))
+                    )
+                })
+                .collect();
+            lines.push(&format!(
+                "return {} ? {}[i] : ({}) -1;",
+                comparisons.join(" & "),
+                self.sym("values"),
+                self.uint(32)
+            ));
+        } else {
+            let entries = self.sym("entries");
+            let key_lens = self.sym("key_lens");
+            let values = self.sym("values");
+            let (key_len, key_ptr, value) = match key_layout {
+                KeyLayout::Auto => unreachable!(),
+                KeyLayout::Entries => (
+                    format!("{entries}[i].len"),
+                    format!("{entries}[i].key"),
+                    format!("{entries}[i].value"),
+                ),
+                KeyLayout::Pool => (
+                    format!("{key_lens}[i]"),
+                    format!("{} + {}[i]", self.sym("key_pool"), self.sym("key_offsets")),
+                    format!("{values}[i]"),
+                ),
+                KeyLayout::Inline => (
+                    format!("{key_lens}[i]"),
+                    format!("{}[i]", self.sym("key_chars")),
+                    format!("{values}[i]"),
+                ),
+            };
+            match self.config.key_access {
+                KeyAccess::Contiguous => lines.extend(&[
+                    &format!("if (len == {key_len} && memcmp(key, {key_ptr}, len) == 0) {{"),
+                    &format!("\treturn {value};"),
+                    "}",
+                    "return -1;",
+                ]),
+                KeyAccess::Callback => lines.extend(&[
+                    &format!("const char* slot_key = {key_ptr};"),
+                    "size_t j;",
+                    &format!("if (len != {key_len}) {{"),
+                    "\treturn -1;",
+                    "}",
+                    "for (j = 0; j < len; j++) {",
+                    "\tif (key_at(ctx, j) != (unsigned char) slot_key[j]) {",
+                    "\t\treturn -1;",
+                    "\t}",
+                    "}",
+                    &format!("return {value};"),
+                ]),
+            }
+        }
+
+        lines.dedent(lookup_indent);
+        lines.push("}");
+    }
+
+    fn compile_definitions(&self, lines: &mut Lines) {
+        let phf = &self.phf;
+        let tac = &phf.tac;
+
+        let key_used = reads_key(tac);
+        let helpers = Helpers::new(tac);
+
+        let packed_keys = self.key_layout() == KeyLayout::Inline
+            && self.fixed_key_len().is_some()
+            && self.tree().is_none();
+        let uses_inline =
+            self.config.output_style == OutputStyle::Header || packed_keys || !helpers.is_empty();
+        if self.config.standard == CStandard::C89 && uses_inline {
+            lines.push_empty();
+            Self::compile_inline_macro(lines);
+        }
+        if !helpers.str_sums.is_empty() && !self.vectorize_str_sum() {
+            lines.push_empty();
+            Self::compile_no_vectorize_macro(lines);
+        }
+
+        if self.config.output_style != OutputStyle::Header && self.tree().is_none() {
+            lines.push_empty();
+            self.compile_keys(lines);
+        }
+        self.compile_packs(lines);
+        for &mask in &helpers.str_sums {
+            lines.push_empty();
+            self.compile_str_sum(lines, mask);
+        }
+        for &(mask, head, tail) in &helpers.str_sums_bounded {
+            lines.push_empty();
+            self.compile_str_sum_bounded(lines, mask, head, tail);
+        }
+        for &seed in &helpers.str_hashes {
+            lines.push_empty();
+            self.compile_str_hash(lines, seed);
+        }
+
+        lines.push_empty();
+        if self.config.key_access == KeyAccess::Callback {
+            self.compile_needed_positions(lines, tac, &phf.tables);
+        }
+        lines.push(&format!(
+            "{}{} {}({}) {{",
+            self.linkage(),
+            self.uint(32),
+            self.sym("hash"),
+            self.key_params(),
+        ));
+        let hash_indent = lines.indent();
+
+        let (arrays, table_refs) = pack_tables(&phf.tables);
+        for (bits, name, data) in arrays {
+            let declaration = format!("static const {} {name}[{}]", self.uint(bits), data.len());
+            let elements = data.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            Self::compile_array(lines, &declaration, &elements)
         }
 
         let exprs = tac.unflatten_dag().0;
+        let c89 = self.config.standard == CStandard::C89;
+        if c89 {
+            for i in 0..exprs.len() - 1 {
+                lines.push(&format!("{} x{i};", self.uint(32)));
+            }
+        }
+        if !key_used {
+            match self.config.key_access {
+                KeyAccess::Contiguous => lines.push("(void) key;"),
+                KeyAccess::Callback => lines.extend(&["(void) key_at;", "(void) ctx;"]),
+            }
+        }
+
+        lines.push(&format!("if ({}) {{", self.len_out_of_range()));
+        lines.push("\treturn 0;");
+        lines.push("}");
+
         for (i, expr) in exprs.iter().enumerate() {
-            let expr_str = self.expr_to_c_expr(expr).cleaned().to_string();
+            let expr_str = lower(self, phf, &table_refs, expr).to_string();
             if i == exprs.len() - 1 {
                 lines.push(&format!("return {expr_str};"));
+            } else if c89 {
+                lines.push(&format!("x{i} = {expr_str};"));
             } else {
-                lines.push(&format!("uint32_t x{i} = {expr_str};"));
+                lines.push(&format!("{} x{i} = {expr_str};", self.uint(32)));
             }
         }
 
         lines.dedent(hash_indent);
         lines.push("}");
 
+        lines.push_empty();
+        self.compile_lookup(lines);
+    }
+
+    fn compile_inline_macro(lines: &mut Lines) {
+        lines.extend(&[
+            "#ifndef SECO_INLINE",
+            "#if defined(__cplusplus) || (defined(__STDC_VERSION__) && __STDC_VERSION__ >= 199901L)",
+            "#define SECO_INLINE inline",
+            "#elif defined(__GNUC__)",
+            "#define SECO_INLINE __inline__",
+            "#elif defined(_MSC_VER)",
+            "#define SECO_INLINE __inline",
+            "#else",
+            "#define SECO_INLINE",
+            "#endif",
+            "#endif",
+        ]);
+    }
+
+    // Keeps GCC from vectorizing loops that are faster unrolled by hand.
+    fn compile_no_vectorize_macro(lines: &mut Lines) {
         lines.extend(&[
+            "#ifndef SECO_NO_VECTORIZE",
+            "#if defined(__GNUC__) && !defined(__clang__)",
+            "#define SECO_NO_VECTORIZE __attribute__((optimize(\"no-tree-vectorize\")))",
+            "#else",
+            "#define SECO_NO_VECTORIZE",
+            "#endif",
+            "#endif",
+        ]);
+    }
+
+    // The types, typedef and prototypes that callers need.
+    fn compile_declarations(&self, lines: &mut Lines) {
+        if let Some(ref int_types) = self.config.int_types {
+            lines.push_empty();
+            let used_uint_bits = self.used_uint_bits.borrow().clone();
+            for (bits, name) in [
+                (8, &int_types.u8),
+                (16, &int_types.u16),
+                (32, &int_types.u32),
+                (64, &int_types.u64),
+            ] {
+                if used_uint_bits.contains(&bits) {
+                    lines.push(&format!("typedef {name} {};", self.uint(bits)));
+                }
+            }
+        }
+        if self.config.key_access == KeyAccess::Callback {
+            lines.push_empty();
+            lines.push(&format!(
+                "typedef unsigned char (*{})(const void* ctx, size_t i);",
+                self.sym("key_at_fn")
+            ));
+        }
+        if self.config.output_style == OutputStyle::Split {
+            lines.push_empty();
+            for name in ["hash", "lookup"] {
+                lines.push(&format!(
+                    "{} {}({});",
+                    self.uint(32),
+                    self.sym(name),
+                    self.key_params()
+                ));
+            }
+        }
+    }
+
+    fn header_guard(&self) -> String {
+        format!("{}{}", self.config.prefix, self.config.header_name)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+
+    fn compile_header(&self, lines: &mut Lines) {
+        let guard = self.header_guard();
+        lines.extend(&[
+            &format!("#ifndef {guard}"),
+            &format!("#define {guard}"),
             "",
-            &format!("uint32_t lookup({key_declaration}, {len_declaration}) {{"),
-            "\tuint32_t i = hash(key, len);",
-            "\tif (len == entries[i].len && memcmp(key, entries[i].key, len) == 0) {",
-            "\t\treturn entries[i].value;",
-            "\t}",
-            "\treturn -1;",
+            "#include <stddef.h>",
+        ]);
+        if self.config.int_types.is_none() {
+            lines.push("#include <stdint.h>");
+        }
+        if self.config.output_style == OutputStyle::Header {
+            lines.push("#include <string.h>");
+        }
+        lines.extend(&["", "#ifdef __cplusplus", "extern \"C\" {", "#endif"]);
+        self.compile_declarations(lines);
+        if self.config.output_style == OutputStyle::Header {
+            self.compile_definitions(lines);
+        }
+        lines.extend(&[
+            "",
+            "#ifdef __cplusplus",
             "}",
+            "#endif",
+            "",
+            &format!("#endif {}", self.comment(&guard)),
         ]);
+    }
+
+    fn compile_source(&self, lines: &mut Lines) {
+        match self.config.output_style {
+            OutputStyle::Source => {
+                lines.push("#include <stddef.h>");
+                if self.config.int_types.is_none() {
+                    lines.push("#include <stdint.h>");
+                }
+                lines.push("#include <string.h>");
+                self.compile_declarations(lines);
+            }
+            OutputStyle::Split => lines.extend(&[
+                &format!("#include \"{}\"", self.config.header_name),
+                "",
+                "#include <string.h>",
+            ]),
+            OutputStyle::Header => unreachable!(),
+        }
+        self.compile_definitions(lines);
+    }
 
+    fn finish(lines: Lines) -> String {
         let mut lines: Vec<String> = lines.into();
         for line in lines.iter_mut() {
             line.push('\n');
         }
         lines.join("")
     }
+
+    pub fn emit(&self) -> Vec<OutputFile> {
+        let new_lines = || Lines::new(80, 4, true);
+        // The typedefs come before the code that uses them, so the
+        // definitions are compiled once up front to find the widths used.
+        if self.config.int_types.is_some() {
+            self.compile_definitions(&mut new_lines());
+        }
+        let mut files = Vec::new();
+        if self.config.output_style != OutputStyle::Source {
+            let mut lines = new_lines();
+            self.compile_header(&mut lines);
+            files.push(OutputFile {
+                extension: "h".into(),
+                contents: Self::finish(lines),
+            });
+        }
+        if self.config.output_style != OutputStyle::Header {
+            let mut lines = new_lines();
+            self.compile_source(&mut lines);
+            files.push(OutputFile {
+                extension: "c".into(),
+                contents: Self::finish(lines),
+            });
+        }
+        files
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::search::{search, SearchOptions};
+    use std::{
+        fs,
+        io::Write,
+        process::{Command, Stdio},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    pub(super) fn dataset_keys(text: &str) -> Vec<Vec<u32>> {
+        text.lines()
+            .map(|line| line.bytes().map(u32::from).collect())
+            .collect()
+    }
+
+    fn emit(keys: &[Vec<u32>], options: &SearchOptions) -> String {
+        let spec = Spec::new(keys.to_vec());
+        let phf = search(&spec, options).unwrap();
+        let mut files = CBackend::new(spec, phf, CBackendConfig::default()).emit();
+        files.remove(0).contents
+    }
+
+    // Whether the C output shifts anything far enough to overflow an int.
+    fn has_wide_shift(contents: &str) -> bool {
+        (24..32).any(|n| contents.contains(&format!("<< {n})")))
+    }
+
+    // Compiles the C output with a main that looks up every key, and runs it
+    // with undefined behavior trapping, if a C compiler is available.
+    pub(super) fn run_with_ubsan(contents: &str, keys: &[Vec<u32>]) {
+        let formatter = CStrFormatter::new();
+        let mut program = format!("{contents}\nint main(void) {{\n");
+        for (i, key) in keys.iter().enumerate() {
+            let bytes = key.iter().map(|&b| u8::try_from(b).unwrap()).collect();
+            let key = formatter.format(bytes);
+            program.push_str(&format!(
+                "    if (lookup({key}, sizeof({key}) - 1) != {i}) {{\n        return 1;\n    }}\n"
+            ));
+        }
+        program.push_str("    return 0;\n}\n");
+
+        // Tests run in parallel, so each program gets its own file.
+        static PROGRAMS: AtomicUsize = AtomicUsize::new(0);
+        let n = PROGRAMS.fetch_add(1, Ordering::Relaxed);
+        let exe = std::env::temp_dir().join(format!("secohash-ubsan-{}-{n}", std::process::id()));
+        let Ok(mut cc) = Command::new("cc")
+            .args([
+                "-fsanitize=undefined",
+                "-fno-sanitize-recover",
+                "-x",
+                "c",
+                "-",
+                "-o",
+            ])
+            .arg(&exe)
+            .stdin(Stdio::piped())
+            .spawn()
+        else {
+            return;
+        };
+        cc.stdin
+            .take()
+            .unwrap()
+            .write_all(program.as_bytes())
+            .unwrap();
+        assert!(cc.wait().unwrap().success());
+        let output = Command::new(&exe).output().unwrap();
+        fs::remove_file(&exe).unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    fn emit_c89(keys: &[&str], prefix: &str, key_layout: KeyLayout) -> String {
+        let keys: Vec<Vec<u32>> = keys
+            .iter()
+            .map(|key| key.bytes().map(u32::from).collect())
+            .collect();
+        let spec = Spec::new(keys);
+        let phf = search(&spec, &SearchOptions::default()).unwrap();
+        let config = CBackendConfig {
+            key_layout,
+            prefix: prefix.into(),
+            standard: CStandard::C89,
+            int_types: Some(IntTypes::default()),
+            ..CBackendConfig::default()
+        };
+        let mut files = CBackend::new(spec, phf, config).emit();
+        assert_eq!(files.len(), 1);
+        files.remove(0).contents
+    }
+
+    #[test]
+    fn test_emit_c89() {
+        let contents = emit_c89(&["if", "else", "while"], "", KeyLayout::Entries);
+        assert!(!contents.contains("stdint.h"));
+        assert!(!contents.contains("//"));
+        assert!(contents.contains("typedef unsigned int seco_u32;"));
+        // Only the widths that the code uses are declared.
+        assert!(!contents.contains("unsigned long long"));
+
+        let contents = emit_c89(&["if", "else", "while"], "kw_", KeyLayout::Entries);
+        assert!(contents.contains("typedef unsigned int kw_u32;"));
+        assert!(!contents.contains("seco_u32"));
+
+        // Compile the output strictly if a C compiler is available. Keys of
+        // one length are packed into integers when inline.
+        for keys in [&["if", "else", "while"][..], &["for", "let", "var"]] {
+            for key_layout in [KeyLayout::Entries, KeyLayout::Pool, KeyLayout::Inline] {
+                let contents = emit_c89(keys, "", key_layout);
+                let Ok(mut cc) = Command::new("cc")
+                    .args([
+                        "-std=c89",
+                        "-pedantic",
+                        "-Werror",
+                        "-fsyntax-only",
+                        "-x",
+                        "c",
+                        "-",
+                    ])
+                    .stdin(Stdio::piped())
+                    .spawn()
+                else {
+                    return;
+                };
+                cc.stdin
+                    .take()
+                    .unwrap()
+                    .write_all(contents.as_bytes())
+                    .unwrap();
+                assert!(cc.wait().unwrap().success(), "{key_layout:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_wide_shifts_are_defined() {
+        // This hash shifts a symbol by 26.
+        let keys = dataset_keys(include_str!("../benchmarks/datasets/usa_states.txt"));
+        let options = SearchOptions {
+            seed: Some(7),
+            ..SearchOptions::default()
+        };
+        let contents = emit(&keys, &options);
+        assert!(has_wide_shift(&contents));
+        run_with_ubsan(&contents, &keys);
+    }
+
+    #[test]
+    fn test_seeded_hashes_are_defined() {
+        // Seeds vary the mixer shifts, so each hash is checked for undefined
+        // behavior as well as for finding every key.
+        let keys = dataset_keys(include_str!("../benchmarks/datasets/usa_states.txt"));
+        for seed in 1..=4 {
+            let options = SearchOptions {
+                seed: Some(seed),
+                ..SearchOptions::default()
+            };
+            run_with_ubsan(&emit(&keys, &options), &keys);
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{borrow::Cow, fmt};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CBinOp {
@@ -20,24 +20,123 @@ impl CBinOp {
     }
 }
 
-impl fmt::Display for CBinOp {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use CBinOp::*;
+// How a language spells expressions.
+#[derive(Clone)]
+pub struct Syntax {
+    // The pattern and precedence of Add, Sub, And, Shl and Shr, in which {a}
+    // and {b} stand for the operands. Higher precedence binds tighter.
+    // Operators of equal precedence are only mixed with parentheses, so a
+    // table may put operators that the language ranks differently on one
+    // level to force them.
+    ops: [(Cow<'static, str>, u8); 5],
+    cast: CastStyle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CastStyle {
+    // (T) e
+    Prefix,
+    // T(e)
+    Call,
+}
+
+const fn op(pattern: &'static str, precedence: u8) -> (Cow<'static, str>, u8) {
+    (Cow::Borrowed(pattern), precedence)
+}
 
-        write!(
-            f,
-            "{}",
-            match self {
-                Add => "+",
-                Sub => "-",
-                And => "&",
-                Shl => "<<",
-                Shr => ">>",
+// C compilers warn about shifts and sums mixed without parentheses, so every
+// operation inside another is parenthesized.
+const C_OPS: [(Cow<'static, str>, u8); 5] = [
+    op("{a} + {b}", 4),
+    op("{a} - {b}", 4),
+    op("{a} & {b}", 4),
+    op("{a} << {b}", 4),
+    op("{a} >> {b}", 4),
+];
+
+pub static C: Syntax = Syntax {
+    ops: C_OPS,
+    cast: CastStyle::Prefix,
+};
+
+// Go ranks shifts and & above sums, unlike C, which readers would trip over.
+pub static GO: Syntax = Syntax {
+    ops: C_OPS,
+    cast: CastStyle::Call,
+};
+
+pub static PYTHON: Syntax = Syntax {
+    ops: [
+        op("{a} + {b}", 3),
+        op("{a} - {b}", 3),
+        op("{a} & {b}", 1),
+        op("{a} << {b}", 2),
+        op("{a} >> {b}", 2),
+    ],
+    cast: CastStyle::Call,
+};
+
+// Numbers are doubles, and only >>> treats its operand as unsigned.
+pub static JS: Syntax = Syntax {
+    ops: [
+        op("{a} + {b}", 4),
+        op("{a} - {b}", 4),
+        op("{a} & {b}", 4),
+        op("{a} << {b}", 4),
+        op("{a} >>> {b}", 4),
+    ],
+    cast: CastStyle::Call,
+};
+
+impl Syntax {
+    fn precedence(&self, op: CBinOp) -> u8 {
+        self.ops[op as usize].1
+    }
+
+    // Spells the operator with another pattern, keeping its precedence.
+    pub fn set_op(&mut self, op: CBinOp, pattern: String) {
+        self.ops[op as usize].0 = Cow::Owned(pattern);
+    }
+
+    fn needs_parens(&self, e: &CExpr, outer_op: CBinOp) -> bool {
+        match *e {
+            CExpr::BinOp(op, _, _) => {
+                let (precedence, outer_precedence) =
+                    (self.precedence(op), self.precedence(outer_op));
+                precedence < outer_precedence
+                    || (precedence == outer_precedence && !(op == outer_op && op.commutative()))
             }
-        )
+            CExpr::Cast(_, _) => self.cast == CastStyle::Prefix,
+            _ => false,
+        }
     }
 }
 
+// Replaces each {name} in the pattern in a single pass.
+pub fn fill(pattern: &str, args: &[(&str, &str)]) -> String {
+    let mut s = String::new();
+    let mut rest = pattern;
+    'outer: while let Some(start) = rest.find('{') {
+        s.push_str(&rest[..start]);
+        rest = &rest[start..];
+        for (name, value) in args {
+            if let Some(after) = rest
+                .strip_prefix('{')
+                .and_then(|r| r.strip_prefix(name))
+                .and_then(|r| r.strip_prefix('}'))
+            {
+                s.push_str(value);
+                rest = after;
+                continue 'outer;
+            }
+        }
+        s.push('{');
+        rest = &rest[1..];
+    }
+    s.push_str(rest);
+    s
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum CExpr {
     Var(String),
@@ -93,44 +192,42 @@ impl CExpr {
         self.transform(&mut Self::clean_step)
     }
 
-    pub fn precedence(&self) -> u8 {
-        use CBinOp::*;
-        use CExpr::*;
-        match *self {
-            Var(_) | Imm(_) => 0,
-            Call(_, _) | Index(_, _) => 1,
-            BinOp(Add | Sub | And | Shl | Shr, _, _) => 4,
-            Cast(_, _) => 100,
-        }
+    pub fn display<'a>(&'a self, syntax: &'a Syntax) -> Printed<'a> {
+        Printed { e: self, syntax }
     }
+}
 
-    fn write_with_parens(&self, f: &mut fmt::Formatter, parens: bool) -> fmt::Result {
+// An expression printed in the syntax of some language.
+pub struct Printed<'a> {
+    e: &'a CExpr,
+    syntax: &'a Syntax,
+}
+
+impl Printed<'_> {
+    fn write_with_parens(&self, f: &mut fmt::Formatter, e: &CExpr, parens: bool) -> fmt::Result {
+        let e = e.display(self.syntax);
         if parens {
-            write!(f, "({self})")
+            write!(f, "({e})")
         } else {
-            write!(f, "{self}")
+            write!(f, "{e}")
         }
     }
 
-    fn needs_parens_in_bin_op(&self, bin_op: &CExpr) -> bool {
-        use CExpr::*;
-        let BinOp(outer_op, _, _) = bin_op else {
-            panic!();
-        };
-        let mut parens = self.precedence() >= bin_op.precedence();
-        if let BinOp(op, _, _) = self {
-            if op == outer_op && op.commutative() {
-                parens = false;
-            }
+    fn with_parens(&self, e: &CExpr, parens: bool) -> String {
+        let e = e.display(self.syntax);
+        if parens {
+            format!("({e})")
+        } else {
+            e.to_string()
         }
-        parens
     }
 }
 
-impl fmt::Display for CExpr {
+impl fmt::Display for Printed<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use CExpr::*;
-        match self {
+        let syntax = self.syntax;
+        match self.e {
             Var(s) => write!(f, "{s}"),
             Imm(n) => write!(f, "{n}"),
             Call(name, args) => {
@@ -139,26 +236,36 @@ impl fmt::Display for CExpr {
                     if i > 0 {
                         write!(f, ", ")?;
                     }
-                    write!(f, "{arg}")?;
+                    write!(f, "{}", arg.display(syntax))?;
                 }
                 write!(f, ")")
             }
             Index(name, e) => {
-                write!(f, "{name}[{e}]")
-            }
-            Cast(t, e) => {
-                write!(f, "({t}) ")?;
-                e.write_with_parens(f, e.precedence() > 1)
+                write!(f, "{name}[{}]", e.display(syntax))
             }
+            Cast(t, e) => match syntax.cast {
+                CastStyle::Prefix => {
+                    write!(f, "({t}) ")?;
+                    self.write_with_parens(f, e, matches!(**e, BinOp(..) | Cast(..)))
+                }
+                CastStyle::Call => write!(f, "{t}({})", e.display(syntax)),
+            },
             BinOp(op, left, right) => {
-                left.write_with_parens(f, left.needs_parens_in_bin_op(self))?;
-                write!(f, " {op} ")?;
-                right.write_with_parens(f, right.needs_parens_in_bin_op(self))
+                let a = self.with_parens(left, syntax.needs_parens(left, *op));
+                let b = self.with_parens(right, syntax.needs_parens(right, *op));
+                let pattern = &syntax.ops[*op as usize].0;
+                write!(f, "{}", fill(pattern, &[("a", &a), ("b", &b)]))
             }
         }
     }
 }
 
+impl fmt::Display for CExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.display(&C).fmt(f)
+    }
+}
+
 pub struct CExprBuilder();
 
 impl CExprBuilder {
@@ -223,6 +330,16 @@ impl CExprBuilder {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{
+        backend::{
+            builtin_template,
+            lower::{lower, str_sum_bounded_terms, str_sum_term, Operands},
+            pack_tables, CBackend, CBackendConfig, CppBackend, CppBackendConfig, GoBackend,
+            GoBackendConfig, JsBackend, JsBackendConfig, KeyAccess, PythonBackend, Template,
+        },
+        search::{search, SearchOptions, SelectorFamilies, SelectorLimits},
+        spec::Spec,
+    };
 
     #[test]
     fn test_cleaned() {
@@ -254,6 +371,431 @@ mod test {
         assert_eq!(format!("{e}"), "10 + 20 + 30 + ((40 + 50 + 60) << 1)");
     }
 
+    #[test]
+    fn test_fmt_syntaxes() {
+        let x = CExprBuilder();
+        let e = x.and(
+            x.add(x.cast("uint32", x.var("n")), x.shr(x.var("h"), x.imm(1))),
+            x.imm(15),
+        );
+        assert_eq!(e.to_string(), "(((uint32) n) + (h >> 1)) & 15");
+        assert_eq!(e.display(&GO).to_string(), "(uint32(n) + (h >> 1)) & 15");
+        assert_eq!(e.display(&PYTHON).to_string(), "uint32(n) + (h >> 1) & 15");
+        assert_eq!(e.display(&JS).to_string(), "(uint32(n) + (h >>> 1)) & 15");
+    }
+
+    #[test]
+    fn test_fill() {
+        assert_eq!(fill("{a} +% {b}", &[("a", "{b}"), ("b", "1")]), "{b} +% 1");
+        assert_eq!(fill("f({i}) {x}", &[("i", "2")]), "f(2) {x}");
+    }
+
+    // A language as its reference defines it, independently of the Syntax
+    // that prints it: each operator with its precedence, and how casts look.
+    struct Grammar {
+        ops: &'static [(&'static str, CBinOp, u8)],
+        cast: CastStyle,
+        // Calls that only convert their argument, which the comparison skips.
+        conversions: &'static [&'static str],
+    }
+
+    // C11 6.5.6-6.5.10, where sums bind tighter than shifts, and shifts
+    // tighter than &.
+    const C_GRAMMAR: Grammar = Grammar {
+        ops: &[
+            ("+", CBinOp::Add, 3),
+            ("-", CBinOp::Sub, 3),
+            ("<<", CBinOp::Shl, 2),
+            (">>", CBinOp::Shr, 2),
+            ("&", CBinOp::And, 1),
+        ],
+        cast: CastStyle::Prefix,
+        conversions: &[],
+    };
+
+    // The Go spec's operator precedence, where shifts and & are
+    // multiplicative.
+    const GO_GRAMMAR: Grammar = Grammar {
+        ops: &[
+            ("+", CBinOp::Add, 1),
+            ("-", CBinOp::Sub, 1),
+            ("<<", CBinOp::Shl, 2),
+            (">>", CBinOp::Shr, 2),
+            ("&", CBinOp::And, 2),
+        ],
+        cast: CastStyle::Call,
+        conversions: &[],
+    };
+
+    // The Python reference's operator precedence table.
+    const PYTHON_GRAMMAR: Grammar = Grammar {
+        ops: &[
+            ("+", CBinOp::Add, 3),
+            ("-", CBinOp::Sub, 3),
+            ("<<", CBinOp::Shl, 2),
+            (">>", CBinOp::Shr, 2),
+            ("&", CBinOp::And, 1),
+        ],
+        cast: CastStyle::Call,
+        conversions: &[],
+    };
+
+    // ECMAScript's grammar, which ranks operators like C.
+    const JS_GRAMMAR: Grammar = Grammar {
+        ops: &[
+            ("+", CBinOp::Add, 3),
+            ("-", CBinOp::Sub, 3),
+            ("<<", CBinOp::Shl, 2),
+            (">>>", CBinOp::Shr, 2),
+            ("&", CBinOp::And, 1),
+        ],
+        cast: CastStyle::Call,
+        conversions: &[],
+    };
+
+    // Zig's precedence table, which ranks the wrapping operators like C.
+    const ZIG_GRAMMAR: Grammar = Grammar {
+        ops: &[
+            ("+%", CBinOp::Add, 3),
+            ("-%", CBinOp::Sub, 3),
+            ("<<", CBinOp::Shl, 2),
+            (">>", CBinOp::Shr, 2),
+            ("&", CBinOp::And, 1),
+        ],
+        cast: CastStyle::Call,
+        conversions: &["@intCast"],
+    };
+
+    // Parses printed expressions back, treating (T) e as a cast and any
+    // other name followed by parentheses as a call. Operators of equal
+    // precedence associate to the left.
+    struct Parser<'a> {
+        tokens: Vec<String>,
+        pos: usize,
+        grammar: &'a Grammar,
+    }
+
+    impl Parser<'_> {
+        fn parse(text: &str, grammar: &Grammar) -> CExpr {
+            let mut tokens = Vec::new();
+            let mut rest = text;
+            while let Some(c) = rest.chars().next() {
+                let len = if c == ' ' {
+                    rest = &rest[1..];
+                    continue;
+                } else if is_name_char(c) {
+                    rest.find(|c| !is_name_char(c)).unwrap_or(rest.len())
+                } else {
+                    grammar
+                        .ops
+                        .iter()
+                        .filter(|(op, _, _)| rest.starts_with(op))
+                        .map(|(op, _, _)| op.len())
+                        .max()
+                        .unwrap_or(1)
+                };
+                tokens.push(rest[..len].to_string());
+                rest = &rest[len..];
+            }
+            let mut parser = Parser {
+                tokens,
+                pos: 0,
+                grammar,
+            };
+            let e = parser.expr(0);
+            assert_eq!(parser.pos, parser.tokens.len(), "trailing tokens in {text}");
+            e
+        }
+
+        fn peek(&self, offset: usize) -> Option<&str> {
+            self.tokens.get(self.pos + offset).map(|t| t.as_str())
+        }
+
+        fn next(&mut self) -> String {
+            self.pos += 1;
+            self.tokens[self.pos - 1].clone()
+        }
+
+        fn expect(&mut self, token: &str) {
+            assert_eq!(self.next(), token);
+        }
+
+        fn expr(&mut self, min_precedence: u8) -> CExpr {
+            let mut e = self.primary();
+            loop {
+                let Some(&(_, op, precedence)) = self
+                    .grammar
+                    .ops
+                    .iter()
+                    .find(|(token, _, _)| self.peek(0) == Some(*token))
+                else {
+                    return e;
+                };
+                if precedence < min_precedence {
+                    return e;
+                }
+                self.next();
+                let right = self.expr(precedence + 1);
+                e = CExpr::BinOp(op, Box::new(e), Box::new(right));
+            }
+        }
+
+        fn primary(&mut self) -> CExpr {
+            let token = self.next();
+            if token == "(" {
+                // Type names may take several words, as in unsigned char.
+                let words = (0..)
+                    .take_while(|&i| {
+                        self.peek(i)
+                            .is_some_and(|t| t.starts_with(|c: char| c.is_ascii_alphabetic()))
+                    })
+                    .count();
+                if self.grammar.cast == CastStyle::Prefix
+                    && words > 0
+                    && self.peek(words) == Some(")")
+                {
+                    let t: Vec<String> = (0..words).map(|_| self.next()).collect();
+                    self.expect(")");
+                    return CExpr::Cast(t.join(" "), Box::new(self.primary()));
+                }
+                let e = self.expr(0);
+                self.expect(")");
+                return e;
+            }
+            if let Ok(n) = token.parse() {
+                return CExpr::Imm(n);
+            }
+            match self.peek(0) {
+                Some("(") => {
+                    self.next();
+                    let mut args = vec![self.expr(0)];
+                    while self.peek(0) == Some(",") {
+                        self.next();
+                        args.push(self.expr(0));
+                    }
+                    self.expect(")");
+                    CExpr::Call(token, args)
+                }
+                Some("[") => {
+                    self.next();
+                    let e = self.expr(0);
+                    self.expect("]");
+                    CExpr::Index(token, Box::new(e))
+                }
+                _ => CExpr::Var(token),
+            }
+        }
+    }
+
+    // Names may be qualified, as in std::uint32_t and key.len, or builtins,
+    // as in @as.
+    fn is_name_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || "_:.@".contains(c)
+    }
+
+    // Chains of one commutative operator are printed without parentheses and
+    // parsed left associative, and T(e) casts read back as calls. Operands
+    // that backends spell as text are parsed, so that they are compared by
+    // how they read in context.
+    fn normalized(e: CExpr, grammar: &Grammar) -> CExpr {
+        fn operands(e: CExpr, op: CBinOp, out: &mut Vec<CExpr>) {
+            match e {
+                CExpr::BinOp(inner, a, b) if inner == op => {
+                    operands(*a, op, out);
+                    operands(*b, op, out);
+                }
+                e => out.push(e),
+            }
+        }
+        e.transform(&mut |e| match e {
+            CExpr::Var(text) if !text.chars().all(is_name_char) => {
+                normalized(Parser::parse(&text, grammar), grammar)
+            }
+            CExpr::Cast(t, e) if grammar.cast == CastStyle::Call => CExpr::Call(t, vec![*e]),
+            CExpr::Call(name, mut args)
+                if args.len() == 1 && grammar.conversions.contains(&name.as_str()) =>
+            {
+                args.pop().unwrap()
+            }
+            CExpr::BinOp(op, _, _) if op.commutative() => {
+                let mut out = Vec::new();
+                operands(e, op, &mut out);
+                out.into_iter()
+                    .reduce(|a, b| CExpr::BinOp(op, Box::new(a), Box::new(b)))
+                    .unwrap()
+            }
+            e => e,
+        })
+    }
+
+    fn assert_reparses(e: &CExpr, syntax: &Syntax, grammar: &Grammar) {
+        assert_parses_to(&e.display(syntax).to_string(), e, grammar);
+    }
+
+    fn assert_parses_to(text: &str, e: &CExpr, grammar: &Grammar) {
+        let parsed = Parser::parse(text, grammar);
+        assert_eq!(
+            normalized(parsed, grammar),
+            normalized(e.clone(), grammar),
+            "{text}"
+        );
+    }
+
+    const LANGUAGES: [(&Syntax, &Grammar); 4] = [
+        (&C, &C_GRAMMAR),
+        (&GO, &GO_GRAMMAR),
+        (&PYTHON, &PYTHON_GRAMMAR),
+        (&JS, &JS_GRAMMAR),
+    ];
+
+    fn random_expr(state: &mut u64, depth: u32) -> CExpr {
+        let x = CExprBuilder();
+        *state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let r = (*state >> 33) as u32;
+        if depth == 0 {
+            return if r.is_multiple_of(2) {
+                x.var(["a", "b", "c"][(r / 2 % 3) as usize])
+            } else {
+                x.imm(r % 100)
+            };
+        }
+        match r % 8 {
+            0 => x.call("f".into(), vec![random_expr(state, depth - 1)]),
+            1 => x.index("t", random_expr(state, depth - 1)),
+            2 => x.cast("T", random_expr(state, depth - 1)),
+            3 => random_expr(state, 0),
+            _ => {
+                let ops = [
+                    CBinOp::Add,
+                    CBinOp::Sub,
+                    CBinOp::And,
+                    CBinOp::Shl,
+                    CBinOp::Shr,
+                ];
+                let op = ops[(r / 8 % 5) as usize];
+                let a = random_expr(state, depth - 1);
+                let b = random_expr(state, depth - 1);
+                x.bin_op(op, a, b)
+            }
+        }
+    }
+
+    #[test]
+    fn test_reparse() {
+        let mut state = 1;
+        for (syntax, grammar) in LANGUAGES {
+            for _ in 0..1000 {
+                assert_reparses(&random_expr(&mut state, 4), syntax, grammar);
+            }
+        }
+    }
+
+    // Prints expressions as some backend does.
+    type Printer<'a> = &'a dyn Fn(&CExpr) -> String;
+
+    // The expressions that each backend emits for the hashes of a few kinds
+    // of key sets and for the terms of sums, each with its text and the
+    // grammar of the language.
+    fn emitted_exprs() -> Vec<(CExpr, String, &'static Grammar)> {
+        let datasets = [
+            include_str!("../../benchmarks/datasets/months.txt"),
+            include_str!("../../benchmarks/datasets/usa_states.txt"),
+            include_str!("../../benchmarks/datasets/http_headers.txt"),
+        ];
+        let only = |sum, hash| SelectorFamilies {
+            len: false,
+            index: false,
+            arith: false,
+            table: false,
+            sum,
+            hash,
+        };
+        let limits = [
+            SelectorLimits::default(),
+            SelectorLimits {
+                families: only(true, false),
+                ..SelectorLimits::default()
+            },
+            SelectorLimits {
+                sum_bound: Some(2),
+                families: only(true, false),
+                ..SelectorLimits::default()
+            },
+            SelectorLimits {
+                families: only(false, true),
+                ..SelectorLimits::default()
+            },
+        ];
+        let c_template = Template::parse(builtin_template("c").unwrap()).unwrap();
+        let zig_template = Template::parse(builtin_template("zig").unwrap()).unwrap();
+        let in_syntax = |syntax: &'static Syntax| move |e: &CExpr| e.display(syntax).to_string();
+        let x = CExprBuilder();
+        let mut exprs = Vec::new();
+        for text in datasets {
+            let keys: Vec<Vec<u32>> = text
+                .lines()
+                .map(|line| line.bytes().map(u32::from).collect())
+                .collect();
+            for selector_limits in limits {
+                let spec = Spec::new(keys.clone());
+                let options = SearchOptions {
+                    selector_limits,
+                    ..SearchOptions::default()
+                };
+                let Ok(phf) = search(&spec, &options) else {
+                    continue;
+                };
+                let (_, table_refs) = pack_tables(&phf.tables);
+                let hash_exprs = phf.tac.unflatten_dag().0;
+                let backend = |config| CBackend::new(spec.clone(), phf.clone(), config);
+                let c = backend(CBackendConfig::default());
+                let c_callback = backend(CBackendConfig {
+                    key_access: KeyAccess::Callback,
+                    ..CBackendConfig::default()
+                });
+                let cpp = CppBackend::new(spec.clone(), phf.clone(), CppBackendConfig::default());
+                let go = GoBackend::new(spec.clone(), phf.clone(), GoBackendConfig::default());
+                let python = PythonBackend::new(spec.clone(), phf.clone());
+                let js = JsBackend::new(spec.clone(), phf.clone(), JsBackendConfig::default());
+                let targets: [(&dyn Operands, Printer, &Grammar); 8] = [
+                    (&c, &in_syntax(&C), &C_GRAMMAR),
+                    (&c_callback, &in_syntax(&C), &C_GRAMMAR),
+                    (&cpp, &in_syntax(&C), &C_GRAMMAR),
+                    (&go, &in_syntax(&GO), &GO_GRAMMAR),
+                    (&python, &in_syntax(&PYTHON), &PYTHON_GRAMMAR),
+                    (&js, &in_syntax(&JS), &JS_GRAMMAR),
+                    (&c_template, &|e| c_template.print(e), &C_GRAMMAR),
+                    (&zig_template, &|e| zig_template.print(e), &ZIG_GRAMMAR),
+                ];
+                for (target, print, grammar) in targets {
+                    let mut target_exprs: Vec<CExpr> = hash_exprs
+                        .iter()
+                        .map(|expr| lower(target, &phf, &table_refs, expr))
+                        .collect();
+                    target_exprs.push(str_sum_term(target, 5, x.var("i")));
+                    let terms = str_sum_bounded_terms(target, target.str_len(), 3, 2, 2);
+                    target_exprs.extend(terms.into_iter().map(|(_, term)| term));
+                    for e in target_exprs {
+                        let text = print(&e);
+                        exprs.push((e, text, grammar));
+                    }
+                }
+            }
+        }
+        exprs
+    }
+
+    #[test]
+    fn test_reparse_emitted() {
+        let exprs = emitted_exprs();
+        assert!(exprs.len() > 100);
+        for (e, text, grammar) in &exprs {
+            assert_parses_to(text, e, grammar);
+        }
+    }
+
     #[test]
     fn test_fmt_cast() {
         let x = CExprBuilder();
@@ -43,6 +43,45 @@ impl CStrFormatter {
         s.push('"');
         s
     }
+
+    // Format the bytes as adjacent string literals, each at most max_len
+    // characters long where possible.
+    pub fn format_split(&self, bytes: Vec<u8>, max_len: usize) -> Vec<String> {
+        let mut literals = Vec::new();
+        let mut s = String::from('"');
+        for &b in &bytes {
+            let escaped = &self.char_escape_table[usize::from(b)];
+            if s.len() > 1 && s.len() + escaped.len() + 1 > max_len {
+                s.push('"');
+                literals.push(s);
+                s = String::from('"');
+            }
+            s.push_str(escaped);
+        }
+        s.push('"');
+        literals.push(s);
+        literals
+    }
+}
+
+// A double quoted literal in which bytes outside printable ASCII are \x
+// escaped. Go, Python and JavaScript read it the same way, unlike C, where \x
+// takes any number of digits.
+pub fn hex_escaped(bytes: &[u32]) -> String {
+    let mut s = String::from('"');
+    for &b in bytes {
+        match u8::try_from(b).unwrap() {
+            b'"' => s.push_str("\\\""),
+            b'\\' => s.push_str("\\\\"),
+            b'\n' => s.push_str("\\n"),
+            b'\r' => s.push_str("\\r"),
+            b'\t' => s.push_str("\\t"),
+            b @ 0x20..=0x7e => s.push(char::from(b)),
+            b => s.push_str(&format!("\\x{b:02x}")),
+        }
+    }
+    s.push('"');
+    s
 }
 
 #[cfg(test)]
@@ -63,4 +102,16 @@ mod test {
         let formatted = CStrFormatter::new().format(bytes);
         assert_eq!(formatted, r#""\?\"\\\n\r\t a~\000\177""#);
     }
+
+    #[test]
+    fn test_split() {
+        let formatted = CStrFormatter::new().format_split(b"abcd\nef".to_vec(), 6);
+        assert_eq!(formatted, vec!["\"abcd\"", "\"\\nef\""]);
+    }
+
+    #[test]
+    fn test_hex_escaped() {
+        let bytes: Vec<u32> = b"a\"\\\n\x7f\xff".iter().map(|&b| b.into()).collect();
+        assert_eq!(hex_escaped(&bytes), r#""a\"\\\n\x7f\xff""#);
+    }
 }
@@ -0,0 +1,344 @@
+use super::{
+    c_expr::{CExpr, CExprBuilder},
+    c_str_formatter::CStrFormatter,
+    lines::Lines,
+    lower::{lower, str_sum_bounded_terms, str_sum_term, Helpers, Operands},
+    pack_tables, CBackend, OutputFile,
+};
+use crate::{ir::Tac, search::Phf, spec::Spec, util::to_u32};
+
+pub struct CppBackendConfig {
+    // The namespace holding hash() and lookup(), possibly nested with "::".
+    pub namespace: String,
+}
+
+impl Default for CppBackendConfig {
+    fn default() -> Self {
+        CppBackendConfig {
+            namespace: "phf".into(),
+        }
+    }
+}
+
+// Emits a C++17 header in which everything is constexpr, so that lookups of
+// constant keys can be evaluated at compile time.
+pub struct CppBackend {
+    spec: Spec,
+    phf: Phf,
+    config: CppBackendConfig,
+}
+
+impl Operands for CppBackend {
+    fn str_get(&self, i: CExpr) -> CExpr {
+        let x = CExprBuilder();
+        x.call("detail::symbol".into(), vec![x.var("key"), i])
+    }
+
+    fn str_len(&self) -> CExpr {
+        let x = CExprBuilder();
+        x.cast("std::uint32_t", x.var("len"))
+    }
+
+    fn str_sum(&self, mask: u32) -> CExpr {
+        let x = CExprBuilder();
+        x.call(format!("detail::str_sum_{mask}"), vec![x.var("key")])
+    }
+
+    fn str_sum_bounded(&self, mask: u32, head: u32, tail: u32) -> CExpr {
+        let x = CExprBuilder();
+        x.call(
+            format!("detail::str_sum_{mask}_{head}_{tail}"),
+            vec![x.var("key")],
+        )
+    }
+
+    fn str_hash(&self, seed: u32) -> CExpr {
+        let x = CExprBuilder();
+        x.call(format!("detail::str_hash_{seed}"), vec![x.var("key")])
+    }
+
+    fn table_get(&self, table: &str, i: CExpr) -> CExpr {
+        CExprBuilder().index(table, i)
+    }
+}
+
+impl CppBackend {
+    pub fn new(spec: Spec, phf: Phf, config: CppBackendConfig) -> CppBackend {
+        CppBackend { spec, phf, config }
+    }
+
+    // Symbols are read as unsigned, like the search assumes, and widened so
+    // that shifting them cannot overflow.
+    fn compile_symbol(lines: &mut Lines) {
+        lines.extend(&[
+            "constexpr std::uint32_t symbol(std::string_view key, std::size_t i) noexcept {",
+            "\treturn (unsigned char) key[i];",
+            "}",
+        ]);
+    }
+
+    fn compile_str_sum(&self, lines: &mut Lines, mask: u32) {
+        let x = CExprBuilder();
+        lines.push(&format!(
+            "constexpr std::uint32_t str_sum_{mask}(std::string_view key) noexcept {{"
+        ));
+        lines.push("\tstd::uint32_t sum = 0;");
+        lines.push("\tfor (std::size_t i = 0; i < key.size(); i++) {");
+        let term = str_sum_term(self, mask, x.var("i"));
+        lines.push(&format!("\t\tsum += {term};"));
+        lines.push("\t}");
+        lines.push("\treturn sum;");
+        lines.push("}");
+    }
+
+    fn compile_str_sum_bounded(&self, lines: &mut Lines, mask: u32, head: u32, tail: u32) {
+        let x = CExprBuilder();
+        lines.push(&format!(
+            "constexpr std::uint32_t str_sum_{mask}_{head}_{tail}(std::string_view key) noexcept {{"
+        ));
+        let body_indent = lines.indent();
+        lines.push("std::size_t len = key.size();");
+        lines.push("std::uint32_t sum = 0;");
+        for (len_above, term) in str_sum_bounded_terms(self, x.var("len"), mask, head, tail) {
+            lines.push(&format!("sum += len > {len_above} ? {term} : 0;"));
+        }
+        lines.push("return sum;");
+        lines.dedent(body_indent);
+        lines.push("}");
+    }
+
+    fn compile_str_hash(&self, lines: &mut Lines, seed: u32) {
+        let x = CExprBuilder();
+        lines.extend(&[
+            &format!("constexpr std::uint32_t str_hash_{seed}(std::string_view key) noexcept {{"),
+            &format!("\tstd::uint32_t h = {seed};"),
+            "\tfor (std::size_t i = 0; i < key.size(); i++) {",
+            &format!("\t\th += {};", self.str_get(x.var("i"))),
+            "\t\th += h << 10;",
+            "\t\th ^= h >> 6;",
+            "\t}",
+            "\th += h << 3;",
+            "\th ^= h >> 11;",
+            "\th += h << 15;",
+            "\treturn h;",
+            "}",
+        ]);
+    }
+
+    fn compile_keys(&self, lines: &mut Lines) {
+        let key_table = &self.phf.key_table;
+        let str_formatter = CStrFormatter::new();
+        let keys: Vec<String> = key_table
+            .iter()
+            .map(|key| {
+                let literal = str_formatter.format(CBackend::key_bytes(key));
+                format!("{{{literal}, {}}}", key.len())
+            })
+            .collect();
+        CBackend::compile_array(
+            lines,
+            &format!(
+                "inline constexpr std::string_view keys[{}]",
+                key_table.len()
+            ),
+            &keys,
+        );
+
+        let values: Vec<String> = (0..key_table.len())
+            .map(|i| self.phf.ordinals[i].map_or(0, to_u32).to_string())
+            .collect();
+        CBackend::compile_array(
+            lines,
+            &format!("inline constexpr std::uint32_t values[{}]", key_table.len()),
+            &values,
+        );
+    }
+
+    fn compile_hash(&self, lines: &mut Lines, tac: &Tac, table_refs: &[(String, u32)]) {
+        lines.push("constexpr std::uint32_t hash(std::string_view key) noexcept {");
+        let hash_indent = lines.indent();
+        lines.push("std::size_t len = key.size();");
+
+        let min = self.spec.min_interpreted_key_len;
+        let max = self.spec.max_interpreted_key_len;
+        let condition = if min == max {
+            format!("len != {min}")
+        } else {
+            format!("len < {min} || len > {max}")
+        };
+        lines.push(&format!("if ({condition}) {{"));
+        lines.push("\treturn 0;");
+        lines.push("}");
+
+        let exprs = tac.unflatten_dag().0;
+        for (i, expr) in exprs.iter().enumerate() {
+            let expr_str = lower(self, &self.phf, table_refs, expr).to_string();
+            if i == exprs.len() - 1 {
+                lines.push(&format!("return {expr_str};"));
+            } else {
+                lines.push(&format!("std::uint32_t x{i} = {expr_str};"));
+            }
+        }
+
+        lines.dedent(hash_indent);
+        lines.push("}");
+    }
+
+    // Unused slots hold either an empty key, or a real key that hashes
+    // elsewhere, so they can never match.
+    fn compile_lookup(lines: &mut Lines) {
+        lines.extend(&[
+            "constexpr std::optional<std::uint32_t> lookup(std::string_view key) noexcept {",
+            "\tstd::uint32_t i = hash(key);",
+            "\tif (detail::keys[i] == key) {",
+            "\t\treturn detail::values[i];",
+            "\t}",
+            "\treturn std::nullopt;",
+            "}",
+        ]);
+    }
+
+    fn compile(&self, lines: &mut Lines) {
+        let phf = &self.phf;
+        let tac = &phf.tac;
+
+        lines.extend(&[
+            "#pragma once",
+            "",
+            "#include <cstddef>",
+            "#include <cstdint>",
+            "#include <optional>",
+            "#include <string_view>",
+            "",
+            &format!("namespace {} {{", self.config.namespace),
+            "",
+            "namespace detail {",
+            "",
+        ]);
+
+        self.compile_keys(lines);
+
+        let (arrays, mut table_refs) = pack_tables(&phf.tables);
+        for (bits, name, data) in arrays {
+            let declaration = format!("inline constexpr std::uint{bits}_t {name}[{}]", data.len());
+            let elements = data.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            CBackend::compile_array(lines, &declaration, &elements)
+        }
+        for (name, _) in table_refs.iter_mut() {
+            *name = format!("detail::{name}");
+        }
+
+        lines.push_empty();
+        Self::compile_symbol(lines);
+
+        let helpers = Helpers::new(tac);
+        for mask in helpers.str_sums {
+            lines.push_empty();
+            self.compile_str_sum(lines, mask);
+        }
+        for (mask, head, tail) in helpers.str_sums_bounded {
+            lines.push_empty();
+            self.compile_str_sum_bounded(lines, mask, head, tail);
+        }
+        for seed in helpers.str_hashes {
+            lines.push_empty();
+            self.compile_str_hash(lines, seed);
+        }
+
+        lines.extend(&["", "} // namespace detail", ""]);
+        self.compile_hash(lines, tac, &table_refs);
+        lines.push_empty();
+        Self::compile_lookup(lines);
+        lines.extend(&["", &format!("}} // namespace {}", self.config.namespace)]);
+    }
+
+    pub fn emit(&self) -> Vec<OutputFile> {
+        let mut lines = Lines::new(80, 4, true);
+        self.compile(&mut lines);
+        let mut lines: Vec<String> = lines.into();
+        for line in lines.iter_mut() {
+            line.push('\n');
+        }
+        vec![OutputFile {
+            extension: "hpp".into(),
+            contents: lines.join(""),
+        }]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backend::lower::test_hashes,
+        search::{search, SearchOptions},
+    };
+    use std::{
+        fs,
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    #[test]
+    fn test_emit() {
+        let keys: Vec<Vec<u32>> = ["if", "else", "while"]
+            .iter()
+            .map(|key| key.bytes().map(u32::from).collect())
+            .collect();
+        let spec = Spec::new(keys);
+        let phf = search(&spec, &SearchOptions::default()).unwrap();
+        let config = CppBackendConfig {
+            namespace: "lang::keywords".into(),
+        };
+        let files = CppBackend::new(spec, phf, config).emit();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension, "hpp");
+        let contents = &files[0].contents;
+        assert!(contents.contains("namespace lang::keywords {"));
+        assert!(contents.contains(
+            "constexpr std::optional<std::uint32_t> lookup(std::string_view key) noexcept {"
+        ));
+        assert!(contents.contains("{\"while\", 5}"));
+    }
+
+    #[test]
+    fn test_compile() {
+        // Lookups are checked at compile time, for every key and for a key
+        // that is absent, if a C++ compiler is available.
+        let str_formatter = CStrFormatter::new();
+        for (n, (spec, phf, _)) in test_hashes().into_iter().enumerate() {
+            let files = CppBackend::new(spec.clone(), phf, CppBackendConfig::default()).emit();
+            let header =
+                std::env::temp_dir().join(format!("secohash-cpp-{}-{n}.hpp", std::process::id()));
+            fs::write(&header, &files[0].contents).unwrap();
+            let mut program = format!("#include \"{}\"\n", header.display());
+            for (i, key) in spec.keys.iter().enumerate() {
+                let literal = str_formatter.format(CBackend::key_bytes(key));
+                let key = format!("std::string_view({literal}, {})", key.len());
+                program.push_str(&format!(
+                    "static_assert(phf::lookup({key}).has_value());\n\
+                     static_assert(*phf::lookup({key}) == {i}u);\n"
+                ));
+            }
+            program.push_str("static_assert(!phf::lookup(\"not a key\").has_value());\n");
+
+            let Ok(mut cxx) = Command::new("g++")
+                .args(["-std=c++17", "-Wall", "-Wextra", "-Werror"])
+                .args(["-fsyntax-only", "-x", "c++", "-"])
+                .stdin(Stdio::piped())
+                .spawn()
+            else {
+                fs::remove_file(&header).unwrap();
+                return;
+            };
+            cxx.stdin
+                .take()
+                .unwrap()
+                .write_all(program.as_bytes())
+                .unwrap();
+            let status = cxx.wait().unwrap();
+            fs::remove_file(&header).unwrap();
+            assert!(status.success());
+        }
+    }
+}
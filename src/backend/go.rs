@@ -0,0 +1,484 @@
+use super::{
+    c_expr::{CExpr, CExprBuilder, GO},
+    c_str_formatter::hex_escaped,
+    lines::Lines,
+    lower::{lower, str_sum_bounded_terms, str_sum_term, Helpers, Operands},
+    pack_tables, OutputFile,
+};
+use crate::{ir::Tac, search::Phf, spec::Spec, util::to_u32};
+
+pub struct GoBackendConfig {
+    pub package: String,
+}
+
+impl Default for GoBackendConfig {
+    fn default() -> Self {
+        GoBackendConfig {
+            package: "phf".into(),
+        }
+    }
+}
+
+// Emits a Go package. The hash is generic over strings and byte slices, so
+// that neither Hash nor Lookup has to convert its argument.
+pub struct GoBackend {
+    spec: Spec,
+    phf: Phf,
+    config: GoBackendConfig,
+}
+
+// The type parameter constraint of the functions that read the key.
+const KEY_TYPE: &str = "K ~string | ~[]byte";
+
+fn compile_array(lines: &mut Lines, declaration: &str, elements: &[String]) {
+    let one_liner = format!("{declaration}{{{}}}", elements.join(", "));
+    if one_liner.len() <= lines.text_cols() {
+        lines.push(&one_liner);
+        return;
+    }
+
+    lines.push(&format!("{declaration}{{"));
+    let arr_indent = lines.indent();
+    let elements = elements.iter().map(|e| format!("{e},")).collect::<Vec<_>>();
+    lines.fill(&elements);
+    lines.dedent(arr_indent);
+    lines.push("}");
+}
+
+// Go has no implicit conversions.
+fn convert(e: CExpr) -> CExpr {
+    CExprBuilder().cast("uint32", e)
+}
+
+impl Operands for GoBackend {
+    fn str_get(&self, i: CExpr) -> CExpr {
+        convert(CExprBuilder().index("key", i))
+    }
+
+    fn str_len(&self) -> CExpr {
+        convert(CExprBuilder().var("len(key)"))
+    }
+
+    fn str_sum(&self, mask: u32) -> CExpr {
+        let x = CExprBuilder();
+        x.call(format!("strSum{mask}"), vec![x.var("key")])
+    }
+
+    fn str_sum_bounded(&self, mask: u32, head: u32, tail: u32) -> CExpr {
+        let x = CExprBuilder();
+        x.call(format!("strSum{mask}_{head}_{tail}"), vec![x.var("key")])
+    }
+
+    fn str_hash(&self, seed: u32) -> CExpr {
+        let x = CExprBuilder();
+        x.call(format!("strHash{seed}"), vec![x.var("key")])
+    }
+
+    fn table_get(&self, table: &str, i: CExpr) -> CExpr {
+        convert(CExprBuilder().index(table, i))
+    }
+}
+
+impl GoBackend {
+    pub fn new(spec: Spec, phf: Phf, config: GoBackendConfig) -> GoBackend {
+        GoBackend { spec, phf, config }
+    }
+
+    fn compile_str_sum(&self, lines: &mut Lines, mask: u32) {
+        let x = CExprBuilder();
+        let term = str_sum_term(self, mask, x.var("i"));
+        let term = term.display(&GO);
+        lines.extend(&[
+            &format!("func strSum{mask}[{KEY_TYPE}](key K) uint32 {{"),
+            "\tvar sum uint32",
+            "\tfor i := 0; i < len(key); i++ {",
+            &format!("\t\tsum += {term}"),
+            "\t}",
+            "\treturn sum",
+            "}",
+        ]);
+    }
+
+    fn compile_str_hash(&self, lines: &mut Lines, seed: u32) {
+        let x = CExprBuilder();
+        let symbol = self.str_get(x.var("i"));
+        let symbol = symbol.display(&GO);
+        lines.extend(&[
+            &format!("func strHash{seed}[{KEY_TYPE}](key K) uint32 {{"),
+            &format!("\th := uint32({seed})"),
+            "\tfor i := 0; i < len(key); i++ {",
+            &format!("\t\th += {symbol}"),
+            "\t\th += h << 10",
+            "\t\th ^= h >> 6",
+            "\t}",
+            "\th += h << 3",
+            "\th ^= h >> 11",
+            "\th += h << 15",
+            "\treturn h",
+            "}",
+        ]);
+    }
+
+    fn compile_str_sum_bounded(&self, lines: &mut Lines, mask: u32, head: u32, tail: u32) {
+        let x = CExprBuilder();
+        lines.push(&format!(
+            "func strSum{mask}_{head}_{tail}[{KEY_TYPE}](key K) uint32 {{"
+        ));
+        let body_indent = lines.indent();
+        lines.push("var sum uint32");
+        for (len_above, term) in str_sum_bounded_terms(self, x.var("len(key)"), mask, head, tail) {
+            let term = term.display(&GO);
+            lines.extend(&[
+                &format!("if len(key) > {len_above} {{"),
+                &format!("\tsum += {term}"),
+                "}",
+            ]);
+        }
+        lines.push("return sum");
+        lines.dedent(body_indent);
+        lines.push("}");
+    }
+
+    fn compile_keys(&self, lines: &mut Lines) {
+        let key_table = &self.phf.key_table;
+        let keys: Vec<String> = key_table.iter().map(|key| hex_escaped(key)).collect();
+        compile_array(
+            lines,
+            &format!("var keys = [{}]string", key_table.len()),
+            &keys,
+        );
+        let values: Vec<String> = (0..key_table.len())
+            .map(|i| self.phf.ordinals[i].map_or(0, to_u32).to_string())
+            .collect();
+        compile_array(
+            lines,
+            &format!("var values = [{}]uint32", key_table.len()),
+            &values,
+        );
+    }
+
+    fn compile_hash(&self, lines: &mut Lines, tac: &Tac, table_refs: &[(String, u32)]) {
+        lines.push(&format!("func hash[{KEY_TYPE}](key K) uint32 {{"));
+        let hash_indent = lines.indent();
+
+        let min = self.spec.min_interpreted_key_len;
+        let max = self.spec.max_interpreted_key_len;
+        let condition = if min == max {
+            format!("len(key) != {min}")
+        } else {
+            format!("len(key) < {min} || len(key) > {max}")
+        };
+        lines.push(&format!("if {condition} {{"));
+        lines.push("\treturn 0");
+        lines.push("}");
+
+        let exprs = tac.unflatten_dag().0;
+        for (i, expr) in exprs.iter().enumerate() {
+            let expr = lower(self, &self.phf, table_refs, expr);
+            let expr_str = expr.display(&GO);
+            if i == exprs.len() - 1 {
+                lines.push(&format!("return {expr_str}"));
+            } else {
+                lines.push(&format!("x{i} := {expr_str}"));
+            }
+        }
+
+        lines.dedent(hash_indent);
+        lines.push("}");
+    }
+
+    fn compile(&self, lines: &mut Lines) {
+        let phf = &self.phf;
+        let tac = &phf.tac;
+
+        lines.extend(&[
+            "// Code generated by secohash. DO NOT EDIT.",
+            "",
+            &format!("package {}", self.config.package),
+            "",
+        ]);
+
+        self.compile_keys(lines);
+        let (arrays, table_refs) = pack_tables(&phf.tables);
+        for (bits, name, data) in arrays {
+            let elements = data.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            compile_array(
+                lines,
+                &format!("var {name} = [{}]uint{bits}", data.len()),
+                &elements,
+            );
+        }
+
+        let helpers = Helpers::new(tac);
+        for mask in helpers.str_sums {
+            lines.push_empty();
+            self.compile_str_sum(lines, mask);
+        }
+        for (mask, head, tail) in helpers.str_sums_bounded {
+            lines.push_empty();
+            self.compile_str_sum_bounded(lines, mask, head, tail);
+        }
+        for seed in helpers.str_hashes {
+            lines.push_empty();
+            self.compile_str_hash(lines, seed);
+        }
+
+        lines.push_empty();
+        self.compile_hash(lines, tac, &table_refs);
+
+        // Unused slots hold either an empty key, or a real key that hashes
+        // elsewhere, so they can never match.
+        lines.extend(&[
+            "",
+            "// Hash returns the slot of key, which is only meaningful if key is",
+            "// in the set.",
+            "func Hash(key []byte) uint32 {",
+            "\treturn hash(key)",
+            "}",
+            "",
+            "// Lookup returns the index of key in the input, if it is in the set.",
+            "func Lookup(key string) (uint32, bool) {",
+            "\ti := hash(key)",
+            "\tif keys[i] != key {",
+            "\t\treturn 0, false",
+            "\t}",
+            "\treturn values[i], true",
+            "}",
+        ]);
+    }
+
+    pub fn emit(&self) -> Vec<OutputFile> {
+        let mut lines = Lines::new(80, 4, false);
+        self.compile(&mut lines);
+        let mut lines: Vec<String> = lines.into();
+        for line in lines.iter_mut() {
+            line.push('\n');
+        }
+        vec![OutputFile {
+            extension: "go".into(),
+            contents: lines.join(""),
+        }]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backend::{c_expr::CBinOp, lower::test_hashes},
+        ir::str_hash,
+        search::{search, SearchOptions},
+        util::to_usize,
+    };
+    use std::{collections::HashMap, fs, process::Command};
+
+    #[test]
+    fn test_emit() {
+        let keys: Vec<Vec<u32>> = ["if", "else", "while"]
+            .iter()
+            .map(|key| key.bytes().map(u32::from).collect())
+            .collect();
+        let spec = Spec::new(keys);
+        let phf = search(&spec, &SearchOptions::default()).unwrap();
+        let config = GoBackendConfig {
+            package: "keywords".into(),
+        };
+        let files = GoBackend::new(spec, phf, config).emit();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension, "go");
+        let contents = &files[0].contents;
+        assert!(contents.contains("\npackage keywords\n"));
+        assert!(contents.contains("func Hash(key []byte) uint32 {"));
+        assert!(contents.contains("func Lookup(key string) (uint32, bool) {"));
+        assert!(contents.contains("\"while\""));
+    }
+
+    // Evaluates the expressions of the hash as Go does, for one key. How they
+    // are spelled is checked by the reparsing tests in c_expr.
+    struct Interpreter<'a> {
+        backend: &'a GoBackend,
+        arrays: &'a [(u32, String, Vec<u32>)],
+        key: &'a [u32],
+        vars: HashMap<String, u32>,
+    }
+
+    impl Interpreter<'_> {
+        fn eval(&mut self, e: &CExpr) -> u32 {
+            match e {
+                CExpr::Var(name) if name == "len(key)" => to_u32(self.key.len()),
+                CExpr::Var(name) => self.vars[name],
+                CExpr::Imm(n) => *n,
+                CExpr::Cast(t, e) => {
+                    assert_eq!(t, "uint32");
+                    self.eval(e)
+                }
+                CExpr::Index(name, i) => {
+                    let i = to_usize(self.eval(i));
+                    if name == "key" {
+                        return self.key[i];
+                    }
+                    let (_, _, data) = self.arrays.iter().find(|(_, n, _)| n == name).unwrap();
+                    data[i]
+                }
+                CExpr::Call(name, _) => self.call(name),
+                CExpr::BinOp(op, a, b) => {
+                    let (a, b) = (self.eval(a), self.eval(b));
+                    // Go shifts by 32 or more give 0 rather than panicking.
+                    match op {
+                        CBinOp::Add => a.wrapping_add(b),
+                        CBinOp::Sub => a.wrapping_sub(b),
+                        CBinOp::And => a & b,
+                        CBinOp::Shl => a.checked_shl(b).unwrap_or(0),
+                        CBinOp::Shr => a.checked_shr(b).unwrap_or(0),
+                    }
+                }
+            }
+        }
+
+        // Runs a helper as compile_str_sum and its siblings emit it.
+        fn call(&mut self, name: &str) -> u32 {
+            let x = CExprBuilder();
+            let len = self.key.len();
+            if let Some(seed) = name.strip_prefix("strHash") {
+                let symbol = self.backend.str_get(x.var("i"));
+                let symbols: Vec<u32> = (0..len).map(|i| self.eval_at(&symbol, i)).collect();
+                return str_hash(&symbols, seed.parse().unwrap());
+            }
+            let params: Vec<u32> = name
+                .strip_prefix("strSum")
+                .unwrap()
+                .split('_')
+                .map(|param| param.parse().unwrap())
+                .collect();
+            let mut sum = 0u32;
+            match params[..] {
+                [mask] => {
+                    let term = str_sum_term(self.backend, mask, x.var("i"));
+                    for i in 0..len {
+                        sum = sum.wrapping_add(self.eval_at(&term, i));
+                    }
+                }
+                [mask, head, tail] => {
+                    let terms =
+                        str_sum_bounded_terms(self.backend, x.var("len(key)"), mask, head, tail);
+                    for (len_above, term) in terms {
+                        if len > to_usize(len_above) {
+                            sum = sum.wrapping_add(self.eval(&term));
+                        }
+                    }
+                }
+                _ => panic!("{name}"),
+            }
+            sum
+        }
+
+        fn eval_at(&mut self, e: &CExpr, i: usize) -> u32 {
+            self.vars.insert("i".into(), to_u32(i));
+            self.eval(e)
+        }
+    }
+
+    // The test hashes, each with the Go package emitted for it.
+    fn hashes() -> Vec<(GoBackend, Vec<u32>)> {
+        test_hashes()
+            .into_iter()
+            .map(|(spec, phf, slots)| {
+                (GoBackend::new(spec, phf, GoBackendConfig::default()), slots)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_hash_matches_trace() {
+        let hashes = hashes();
+        assert!(hashes.len() >= 10);
+        for (backend, slots) in &hashes {
+            let (arrays, table_refs) = pack_tables(&backend.phf.tables);
+            let exprs: Vec<CExpr> = backend
+                .phf
+                .tac
+                .unflatten_dag()
+                .0
+                .iter()
+                .map(|expr| lower(backend, &backend.phf, &table_refs, expr))
+                .collect();
+            for (key, &slot) in backend.spec.interpreted_keys.iter().zip(slots) {
+                let mut interpreter = Interpreter {
+                    backend,
+                    arrays: &arrays,
+                    key,
+                    vars: HashMap::new(),
+                };
+                let mut hash = 0;
+                for (i, e) in exprs.iter().enumerate() {
+                    hash = interpreter.eval(e);
+                    interpreter.vars.insert(format!("x{i}"), hash);
+                }
+                assert_eq!(hash, slot);
+                assert_eq!(&backend.phf.key_table[to_usize(slot)], key);
+            }
+        }
+    }
+
+    // Runs the emitted packages with go test, if Go is installed.
+    #[test]
+    fn test_go_test() {
+        if Command::new("go").arg("version").output().is_err() {
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("secohash-go-{}", std::process::id()));
+        for (backend, slots) in hashes() {
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("go.mod"), "module phf\n\ngo 1.18\n").unwrap();
+            let contents = &backend.emit()[0].contents;
+            fs::write(dir.join("phf.go"), contents).unwrap();
+            let cases: Vec<String> = backend
+                .spec
+                .interpreted_keys
+                .iter()
+                .zip(&slots)
+                .map(|(key, &slot)| {
+                    let value = backend.phf.ordinals[to_usize(slot)].unwrap();
+                    format!("\t\t{{{}, {slot}, {value}}},\n", hex_escaped(key))
+                })
+                .collect();
+            let test = format!(
+                "package phf\n\
+                 \n\
+                 import \"testing\"\n\
+                 \n\
+                 func TestHash(t *testing.T) {{\n\
+                 \tcases := []struct {{\n\
+                 \t\tkey   string\n\
+                 \t\tslot  uint32\n\
+                 \t\tvalue uint32\n\
+                 \t}}{{\n\
+                 {}\
+                 \t}}\n\
+                 \tfor _, c := range cases {{\n\
+                 \t\tif got := Hash([]byte(c.key)); got != c.slot {{\n\
+                 \t\t\tt.Errorf(\"Hash(%q) = %d, want %d\", c.key, got, c.slot)\n\
+                 \t\t}}\n\
+                 \t\tif value, ok := Lookup(c.key); !ok || value != c.value {{\n\
+                 \t\t\tt.Errorf(\"Lookup(%q) = %d, %v\", c.key, value, ok)\n\
+                 \t\t}}\n\
+                 \t}}\n\
+                 }}\n",
+                cases.concat()
+            );
+            fs::write(dir.join("phf_test.go"), test).unwrap();
+            let output = Command::new("go")
+                .arg("test")
+                .current_dir(&dir)
+                .output()
+                .unwrap();
+            fs::remove_dir_all(&dir).unwrap();
+            assert!(
+                output.status.success(),
+                "{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+}
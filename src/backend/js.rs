@@ -0,0 +1,432 @@
+use super::{
+    c_expr::{CExpr, CExprBuilder, JS},
+    c_str_formatter::hex_escaped,
+    lines::Lines,
+    lower::{lower, str_sum_bounded_terms, str_sum_term, Helpers, Operands},
+    pack_tables, OutputFile,
+};
+use crate::{
+    ir::{BinOp, Expr, Tac},
+    search::Phf,
+    spec::Spec,
+    util::to_u32,
+};
+
+#[derive(Default)]
+pub struct JsBackendConfig {
+    // Emit TypeScript, which only adds type annotations.
+    pub typescript: bool,
+}
+
+// Emits an ES module. Numbers are doubles, but shifts and ands wrap their
+// operands to 32 bits, so only the results of sums and left shifts have to be
+// brought back to u32 with >>> 0, and only where they are used as a number.
+pub struct JsBackend {
+    spec: Spec,
+    phf: Phf,
+    config: JsBackendConfig,
+}
+
+// Whether the value of the converted expression is in the u32 range.
+fn is_u32(expr: &Expr) -> bool {
+    match *expr {
+        Expr::Var(_)
+        | Expr::Reg(_)
+        | Expr::Imm(_)
+        | Expr::StrGet(_)
+        | Expr::StrLen
+        | Expr::StrSum(_)
+        | Expr::StrSumBounded { .. }
+        | Expr::StrHash(_)
+        | Expr::TableGet(_, _)
+        | Expr::TableIndexMask(_)
+        | Expr::HashMask => true,
+        Expr::BinOp(BinOp::Shrl, _, _) => true,
+        // & has a signed result, which is negative only if both operands
+        // have bit 31 set.
+        Expr::BinOp(BinOp::And, ref a, ref b) => is_i32(a) || is_i32(b),
+        Expr::BinOp(BinOp::Add | BinOp::Sub | BinOp::Shll, _, _) => false,
+    }
+}
+
+// Whether the value of the converted expression is below 2^31.
+fn is_i32(expr: &Expr) -> bool {
+    match *expr {
+        Expr::Imm(n) => n < 1 << 31,
+        Expr::StrGet(_) | Expr::StrLen | Expr::TableIndexMask(_) | Expr::HashMask => true,
+        Expr::BinOp(BinOp::And, ref a, ref b) => is_i32(a) || is_i32(b),
+        Expr::BinOp(BinOp::Shrl, _, ref b) => matches!(**b, Expr::Imm(n) if n > 0),
+        _ => false,
+    }
+}
+
+// Wraps the converted expression to u32 if it may be out of range.
+fn as_u32(expr: &Expr, e: CExpr) -> CExpr {
+    let x = CExprBuilder();
+    if is_u32(expr) {
+        e
+    } else {
+        x.shr(e, x.imm(0))
+    }
+}
+
+impl Operands for JsBackend {
+    fn str_get(&self, i: CExpr) -> CExpr {
+        CExprBuilder().index("key", i)
+    }
+
+    fn str_len(&self) -> CExpr {
+        CExprBuilder().var("len")
+    }
+
+    fn str_sum(&self, mask: u32) -> CExpr {
+        let x = CExprBuilder();
+        x.call(format!("strSum{mask}"), vec![x.var("key")])
+    }
+
+    fn str_sum_bounded(&self, mask: u32, head: u32, tail: u32) -> CExpr {
+        let x = CExprBuilder();
+        x.call(format!("strSum{mask}_{head}_{tail}"), vec![x.var("key")])
+    }
+
+    fn str_hash(&self, seed: u32) -> CExpr {
+        let x = CExprBuilder();
+        x.call(format!("strHash{seed}"), vec![x.var("key")])
+    }
+
+    fn table_get(&self, table: &str, i: CExpr) -> CExpr {
+        CExprBuilder().index(table, i)
+    }
+
+    fn index(&self, expr: &Expr, e: CExpr) -> CExpr {
+        as_u32(expr, e)
+    }
+}
+
+impl JsBackend {
+    pub fn new(spec: Spec, phf: Phf, config: JsBackendConfig) -> JsBackend {
+        JsBackend { spec, phf, config }
+    }
+
+    // The type annotation, if emitting TypeScript.
+    fn annotation(&self, t: &str) -> String {
+        if self.config.typescript {
+            format!(": {t}")
+        } else {
+            String::new()
+        }
+    }
+
+    fn compile_array(lines: &mut Lines, declaration: &str, elements: &[String], end: &str) {
+        let one_liner = format!("{declaration}[{}]{end}", elements.join(", "));
+        if one_liner.len() <= lines.text_cols() {
+            lines.push(&one_liner);
+            return;
+        }
+
+        lines.push(&format!("{declaration}["));
+        let arr_indent = lines.indent();
+        let elements = elements.iter().map(|e| format!("{e},")).collect::<Vec<_>>();
+        lines.fill(&elements);
+        lines.dedent(arr_indent);
+        lines.push(&format!("]{end}"));
+    }
+
+    fn compile_str_sum(&self, lines: &mut Lines, mask: u32) {
+        let x = CExprBuilder();
+        let term = str_sum_term(self, mask, x.var("i"));
+        let sum = x.shr(x.add(x.var("sum"), term), x.imm(0));
+        let sum = sum.display(&JS);
+        lines.extend(&[
+            &format!(
+                "function strSum{mask}(key{}){} {{",
+                self.annotation("Uint8Array"),
+                self.annotation("number")
+            ),
+            "\tlet sum = 0;",
+            "\tfor (let i = 0; i < key.length; i++) {",
+            &format!("\t\tsum = {sum};"),
+            "\t}",
+            "\treturn sum;",
+            "}",
+        ]);
+    }
+
+    // Every step is normalized with >>> 0, as the bitwise operators work on
+    // signed 32-bit values.
+    fn compile_str_hash(&self, lines: &mut Lines, seed: u32) {
+        let x = CExprBuilder();
+        let symbol = self.str_get(x.var("i"));
+        let symbol = symbol.display(&JS);
+        lines.extend(&[
+            &format!(
+                "function strHash{seed}(key{}){} {{",
+                self.annotation("Uint8Array"),
+                self.annotation("number")
+            ),
+            &format!("\tlet h = {seed};"),
+            "\tfor (let i = 0; i < key.length; i++) {",
+            &format!("\t\th = (h + {symbol}) >>> 0;"),
+            "\t\th = (h + (h << 10)) >>> 0;",
+            "\t\th = (h ^ (h >>> 6)) >>> 0;",
+            "\t}",
+            "\th = (h + (h << 3)) >>> 0;",
+            "\th = (h ^ (h >>> 11)) >>> 0;",
+            "\treturn (h + (h << 15)) >>> 0;",
+            "}",
+        ]);
+    }
+
+    fn compile_str_sum_bounded(&self, lines: &mut Lines, mask: u32, head: u32, tail: u32) {
+        let x = CExprBuilder();
+        lines.push(&format!(
+            "function strSum{mask}_{head}_{tail}(key{}){} {{",
+            self.annotation("Uint8Array"),
+            self.annotation("number")
+        ));
+        let body_indent = lines.indent();
+        lines.push("const len = key.length;");
+        lines.push("let sum = 0;");
+        for (len_above, term) in str_sum_bounded_terms(self, x.var("len"), mask, head, tail) {
+            let term = term.display(&JS);
+            lines.extend(&[
+                &format!("if (len > {len_above}) {{"),
+                &format!("\tsum += {term};"),
+                "}",
+            ]);
+        }
+        lines.push("return sum >>> 0;");
+        lines.dedent(body_indent);
+        lines.push("}");
+    }
+
+    // The keys are strings with one character per byte, which are compared
+    // against the encoded key in lookup().
+    fn compile_keys(&self, lines: &mut Lines) {
+        let key_table = &self.phf.key_table;
+        let keys: Vec<String> = key_table.iter().map(|key| hex_escaped(key)).collect();
+        Self::compile_array(lines, "const keys = ", &keys, ";");
+        let values: Vec<String> = (0..key_table.len())
+            .map(|i| self.phf.ordinals[i].map_or(0, to_u32).to_string())
+            .collect();
+        Self::compile_array(lines, "const values = new Uint32Array(", &values, ");");
+    }
+
+    fn compile_hash(&self, lines: &mut Lines, tac: &Tac, table_refs: &[(String, u32)]) {
+        lines.push(&format!(
+            "export function hash(key{}){} {{",
+            self.annotation("Uint8Array"),
+            self.annotation("number")
+        ));
+        let hash_indent = lines.indent();
+        lines.push("const len = key.length;");
+
+        let min = self.spec.min_interpreted_key_len;
+        let max = self.spec.max_interpreted_key_len;
+        let condition = if min == max {
+            format!("len !== {min}")
+        } else {
+            format!("len < {min} || len > {max}")
+        };
+        lines.push(&format!("if ({condition}) {{"));
+        lines.push("\treturn 0;");
+        lines.push("}");
+
+        let exprs = tac.unflatten_dag().0;
+        for (i, expr) in exprs.iter().enumerate() {
+            let expr = as_u32(expr, lower(self, &self.phf, table_refs, expr));
+            let expr_str = expr.display(&JS);
+            if i == exprs.len() - 1 {
+                lines.push(&format!("return {expr_str};"));
+            } else {
+                lines.push(&format!("const x{i} = {expr_str};"));
+            }
+        }
+
+        lines.dedent(hash_indent);
+        lines.push("}");
+    }
+
+    // Strings are looked up by their UTF-8 encoding, which is never shorter
+    // than the string, so strings too long to be keys are rejected before
+    // encoding them. Unused slots hold either an empty key, or a real key that
+    // hashes elsewhere, so they can never match.
+    fn compile_lookup(&self, lines: &mut Lines) {
+        let max = self.spec.max_interpreted_key_len;
+        lines.extend(&[
+            &format!(
+                "export function lookup(key{}){} {{",
+                self.annotation("string | Uint8Array"),
+                self.annotation("number | undefined")
+            ),
+            &format!("\tif (typeof key === \"string\" && key.length > {max}) {{"),
+            "\t\treturn undefined;",
+            "\t}",
+            "\tconst bytes = typeof key === \"string\" ? encoder.encode(key) : key;",
+            "\tconst i = hash(bytes);",
+            "\tconst slotKey = keys[i];",
+            "\tif (slotKey.length !== bytes.length) {",
+            "\t\treturn undefined;",
+            "\t}",
+            "\tfor (let j = 0; j < bytes.length; j++) {",
+            "\t\tif (slotKey.charCodeAt(j) !== bytes[j]) {",
+            "\t\t\treturn undefined;",
+            "\t\t}",
+            "\t}",
+            "\treturn values[i];",
+            "}",
+        ]);
+    }
+
+    fn compile(&self, lines: &mut Lines) {
+        let phf = &self.phf;
+        let tac = &phf.tac;
+
+        lines.extend(&["// Code generated by secohash. DO NOT EDIT.", ""]);
+
+        self.compile_keys(lines);
+        let (arrays, table_refs) = pack_tables(&phf.tables);
+        for (bits, name, data) in arrays {
+            let elements = data.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            let declaration = format!("const {name} = new Uint{bits}Array(");
+            Self::compile_array(lines, &declaration, &elements, ");");
+        }
+        lines.push("const encoder = new TextEncoder();");
+
+        let helpers = Helpers::new(tac);
+        for mask in helpers.str_sums {
+            lines.push_empty();
+            self.compile_str_sum(lines, mask);
+        }
+        for (mask, head, tail) in helpers.str_sums_bounded {
+            lines.push_empty();
+            self.compile_str_sum_bounded(lines, mask, head, tail);
+        }
+        for seed in helpers.str_hashes {
+            lines.push_empty();
+            self.compile_str_hash(lines, seed);
+        }
+
+        lines.push_empty();
+        self.compile_hash(lines, tac, &table_refs);
+        lines.push_empty();
+        self.compile_lookup(lines);
+    }
+
+    pub fn emit(&self) -> Vec<OutputFile> {
+        let mut lines = Lines::new(80, 2, true);
+        self.compile(&mut lines);
+        let mut lines: Vec<String> = lines.into();
+        for line in lines.iter_mut() {
+            line.push('\n');
+        }
+        vec![OutputFile {
+            extension: if self.config.typescript { "ts" } else { "js" }.into(),
+            contents: lines.join(""),
+        }]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backend::lower::test_hashes,
+        search::{search, SearchOptions},
+        util::to_usize,
+    };
+    use std::{
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    #[test]
+    fn test_is_u32() {
+        let key_byte = || Box::new(Expr::StrGet(Box::new(Expr::Imm(0))));
+        let shifted = Expr::BinOp(BinOp::Shll, key_byte(), Box::new(Expr::Imm(24)));
+        assert!(!is_u32(&shifted));
+        let masked = Expr::BinOp(
+            BinOp::And,
+            Box::new(shifted.clone()),
+            Box::new(Expr::HashMask),
+        );
+        assert!(is_u32(&masked));
+        let high = Expr::BinOp(BinOp::And, Box::new(shifted), Box::new(Expr::Imm(1 << 31)));
+        assert!(!is_u32(&high));
+    }
+
+    #[test]
+    fn test_emit() {
+        let keys: Vec<Vec<u32>> = ["if", "else", "while"]
+            .iter()
+            .map(|key| key.bytes().map(u32::from).collect())
+            .collect();
+        let spec = Spec::new(keys);
+        let phf = search(&spec, &SearchOptions::default()).unwrap();
+        let config = JsBackendConfig { typescript: true };
+        let files = JsBackend::new(spec, phf, config).emit();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension, "ts");
+        let contents = &files[0].contents;
+        assert!(contents.contains("export function hash(key: Uint8Array): number {"));
+        assert!(contents
+            .contains("export function lookup(key: string | Uint8Array): number | undefined {"));
+        assert!(contents.contains("if (typeof key === \"string\" && key.length > 5) {"));
+        assert!(contents.contains("\"while\""));
+    }
+
+    // Runs the emitted modules with node against Trace, if it is installed.
+    // Keys are looked up both as bytes and, if they are ASCII, as strings.
+    #[test]
+    fn test_run() {
+        let hashes = test_hashes();
+        assert!(hashes.len() >= 10);
+        for (spec, phf, slots) in hashes {
+            let mut cases = Vec::new();
+            for (key, &slot) in spec.interpreted_keys.iter().zip(&slots) {
+                let value = phf.ordinals[to_usize(slot)].unwrap();
+                let bytes: Vec<String> = key.iter().map(|b| b.to_string()).collect();
+                cases.push(format!("\t[[{}], {slot}, {value}],\n", bytes.join(", ")));
+            }
+            let config = JsBackendConfig::default();
+            let module = &JsBackend::new(spec, phf, config).emit()[0].contents;
+            let script = format!(
+                "{module}\n\
+                 const cases = [\n\
+                 {}\
+                 ];\n\
+                 for (const [bytes, slot, value] of cases) {{\n\
+                 \tconst key = new Uint8Array(bytes);\n\
+                 \tconst text = String.fromCharCode(...bytes);\n\
+                 \tconst ascii = bytes.every((b) => b < 128);\n\
+                 \tif (hash(key) !== slot || lookup(key) !== value || (ascii && lookup(text) !== value)) {{\n\
+                 \t\tthrow new Error(text);\n\
+                 \t}}\n\
+                 }}\n\
+                 if (lookup(\"\\xff\".repeat(100)) !== undefined) {{\n\
+                 \tthrow new Error(\"found an absent key\");\n\
+                 }}\n",
+                cases.concat()
+            );
+            let Ok(mut node) = Command::new("node")
+                .args(["--input-type=module", "-"])
+                .stdin(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+            else {
+                return;
+            };
+            node.stdin
+                .take()
+                .unwrap()
+                .write_all(script.as_bytes())
+                .unwrap();
+            let output = node.wait_with_output().unwrap();
+            assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+}
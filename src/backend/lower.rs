@@ -0,0 +1,218 @@
+// Lowering of the hash to CExprs, shared by the backends. A target only says
+// how each operand is spelled, and its Syntax how the operators are.
+
+use super::c_expr::{CBinOp, CExpr, CExprBuilder};
+use crate::{
+    ir::{BinOp, Expr, Instr, Table, Tac, Var},
+    search::Phf,
+    util::to_u32,
+};
+use std::collections::BTreeSet;
+
+pub trait Operands {
+    // The symbol of the key at i, as an unsigned value.
+    fn str_get(&self, i: CExpr) -> CExpr;
+    fn str_len(&self) -> CExpr;
+    // Calls of the helpers that read the whole key.
+    fn str_sum(&self, mask: u32) -> CExpr;
+    fn str_sum_bounded(&self, mask: u32, head: u32, tail: u32) -> CExpr;
+    fn str_hash(&self, seed: u32) -> CExpr;
+    fn table_get(&self, table: &str, i: CExpr) -> CExpr;
+
+    fn var(&self, n: usize) -> CExpr {
+        CExprBuilder().var(format!("x{n}"))
+    }
+
+    // Indices, and the left operand of right shifts, are where bits above the
+    // u32 range would be observed, for targets whose arithmetic does not wrap.
+    fn index(&self, _expr: &Expr, e: CExpr) -> CExpr {
+        e
+    }
+
+    fn shifted_right(&self, _expr: &Expr, e: CExpr) -> CExpr {
+        e
+    }
+}
+
+pub fn lower<T: Operands + ?Sized>(
+    target: &T,
+    phf: &Phf,
+    table_refs: &[(String, u32)],
+    expr: &Expr,
+) -> CExpr {
+    let x = CExprBuilder();
+    let lower = |e: &Expr| lower(target, phf, table_refs, e);
+    match *expr {
+        Expr::Var(Var(n)) => target.var(n),
+        Expr::Reg(_) => panic!(),
+        Expr::Imm(n) => x.imm(n),
+        Expr::StrGet(ref i) => target.str_get(target.index(i, lower(i))),
+        Expr::StrLen => target.str_len(),
+        Expr::StrSum(mask) => target.str_sum(mask),
+        Expr::StrSumBounded { mask, head, tail } => target.str_sum_bounded(mask, head, tail),
+        Expr::StrHash(seed) => target.str_hash(seed),
+        Expr::TableGet(Table(t), ref i) => {
+            let (ref name, base) = table_refs[t];
+            let i = target.index(i, lower(i));
+            target.table_get(name, if base == 0 { i } else { x.add(i, x.imm(base)) })
+        }
+        Expr::TableIndexMask(t) => x.imm(to_u32(phf.tables[t].len() - 1)),
+        Expr::HashMask => x.imm(to_u32(phf.key_table.len() - 1)),
+        Expr::BinOp(op, ref a, ref b) => {
+            let op = match op {
+                BinOp::Add => CBinOp::Add,
+                BinOp::Sub => CBinOp::Sub,
+                BinOp::And => CBinOp::And,
+                BinOp::Shll => CBinOp::Shl,
+                BinOp::Shrl => CBinOp::Shr,
+            };
+            let mut left = lower(a);
+            if op == CBinOp::Shr {
+                left = target.shifted_right(a, left);
+            }
+            x.bin_op(op, left, lower(b))
+        }
+    }
+}
+
+// The symbol at i shifted as StrSum shifts it.
+pub fn str_sum_term<T: Operands + ?Sized>(target: &T, mask: u32, i: CExpr) -> CExpr {
+    let x = CExprBuilder();
+    let shift = match i {
+        CExpr::Imm(i) => x.imm(i & mask),
+        _ => x.and(i.clone(), x.imm(mask)),
+    };
+    x.shl(target.str_get(i), shift).cleaned()
+}
+
+// The terms of a bounded sum, each with the length the key has to exceed for
+// it to be added.
+pub fn str_sum_bounded_terms<T: Operands + ?Sized>(
+    target: &T,
+    len: CExpr,
+    mask: u32,
+    head: u32,
+    tail: u32,
+) -> Vec<(u32, CExpr)> {
+    let x = CExprBuilder();
+    let mut terms = Vec::new();
+    for i in 0..head {
+        terms.push((i, str_sum_term(target, mask, x.imm(i))));
+    }
+    // Positions in the head are not counted again.
+    for j in 0..tail {
+        let i = x.sub(len.clone(), x.imm(j + 1));
+        terms.push((head + j, str_sum_term(target, mask, i)));
+    }
+    terms
+}
+
+// The helper functions that the hash calls, each of which reads the whole
+// key.
+#[derive(Default)]
+pub struct Helpers {
+    pub str_sums: BTreeSet<u32>,
+    pub str_sums_bounded: BTreeSet<(u32, u32, u32)>,
+    pub str_hashes: BTreeSet<u32>,
+}
+
+impl Helpers {
+    pub fn new(tac: &Tac) -> Helpers {
+        let mut helpers = Helpers::default();
+        for instr in tac.instrs() {
+            match *instr {
+                Instr::StrSum(mask) => {
+                    helpers.str_sums.insert(mask);
+                }
+                Instr::StrSumBounded { mask, head, tail } => {
+                    helpers.str_sums_bounded.insert((mask, head, tail));
+                }
+                Instr::StrHash(seed) => {
+                    helpers.str_hashes.insert(seed);
+                }
+                _ => (),
+            }
+        }
+        helpers
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.str_sums.is_empty() && self.str_sums_bounded.is_empty() && self.str_hashes.is_empty()
+    }
+}
+
+// Whether the hash reads the key at all.
+pub fn reads_key(tac: &Tac) -> bool {
+    tac.instrs().iter().any(|i| {
+        matches!(
+            i,
+            Instr::StrGet(_) | Instr::StrSum(_) | Instr::StrSumBounded { .. } | Instr::StrHash(_)
+        )
+    })
+}
+
+// Hashes of several kinds for the backends to check their output against,
+// each with the slot of every interpreted key as Trace computes it.
+#[cfg(test)]
+pub fn test_hashes() -> Vec<(crate::spec::Spec, Phf, Vec<u32>)> {
+    use crate::{
+        ir::{Reg, Trace},
+        search::{search, SearchOptions, SelectorFamilies, SelectorLimits, Strategy},
+        spec::Spec,
+    };
+
+    let datasets = [
+        include_str!("../../benchmarks/datasets/months.txt"),
+        include_str!("../../benchmarks/datasets/usa_states.txt"),
+        include_str!("../../benchmarks/datasets/http_headers.txt"),
+    ];
+    let only = |sum, hash| SelectorFamilies {
+        len: false,
+        index: false,
+        arith: false,
+        table: false,
+        sum,
+        hash,
+    };
+    let selectors = |families, sum_bound| SearchOptions {
+        selector_limits: SelectorLimits {
+            sum_bound,
+            families,
+            ..SelectorLimits::default()
+        },
+        strategy: Strategy::Selectors,
+        ..SearchOptions::default()
+    };
+    let options = [
+        SearchOptions::default(),
+        selectors(only(true, false), None),
+        selectors(only(true, false), Some(2)),
+        selectors(only(false, true), None),
+        SearchOptions {
+            strategy: Strategy::Displacement,
+            ..SearchOptions::default()
+        },
+    ];
+    let mut hashes = Vec::new();
+    for text in datasets {
+        let keys: Vec<Vec<u32>> = text
+            .lines()
+            .map(|line| line.bytes().map(u32::from).collect())
+            .collect();
+        for options in &options {
+            let spec = Spec::new(keys.clone());
+            let Ok(phf) = search(&spec, options) else {
+                continue;
+            };
+            let trace = Trace::new(
+                &spec.interpreted_keys,
+                &phf.tac,
+                &phf.tables,
+                Some(phf.key_table.len()),
+            );
+            let slots = trace[Reg(phf.tac.instrs().len() - 1)].to_vec();
+            hashes.push((spec, phf, slots));
+        }
+    }
+    hashes
+}
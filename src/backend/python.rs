@@ -0,0 +1,361 @@
+use super::{
+    c_expr::{CExpr, CExprBuilder, PYTHON},
+    c_str_formatter::hex_escaped,
+    lines::Lines,
+    lower::{lower, str_sum_bounded_terms, str_sum_term, Helpers, Operands},
+    pack_tables, OutputFile,
+};
+use crate::{
+    ir::{BinOp, Expr, Tac},
+    search::Phf,
+    spec::Spec,
+    util::to_u32,
+};
+
+// Python integers do not wrap, so values that may have left the u32 range are
+// masked wherever their high bits could be observed.
+const U32_MASK: &str = "0xFFFFFFFF";
+
+// Emits a pure Python module computing the same hash as the other targets.
+pub struct PythonBackend {
+    spec: Spec,
+    phf: Phf,
+}
+
+fn compile_tuple(lines: &mut Lines, name: &str, elements: &[String]) {
+    let one_liner = match elements {
+        [element] => format!("{name} = ({element},)"),
+        _ => format!("{name} = ({})", elements.join(", ")),
+    };
+    if one_liner.len() <= lines.text_cols() {
+        lines.push(&one_liner);
+        return;
+    }
+
+    lines.push(&format!("{name} = ("));
+    let arr_indent = lines.indent();
+    let elements = elements.iter().map(|e| format!("{e},")).collect::<Vec<_>>();
+    lines.fill(&elements);
+    lines.dedent(arr_indent);
+    lines.push(")");
+}
+
+// Whether the Python expression that the expression converts to always has a
+// value in the u32 range.
+fn in_u32_range(expr: &Expr) -> bool {
+    match *expr {
+        Expr::Var(_)
+        | Expr::Reg(_)
+        | Expr::Imm(_)
+        | Expr::StrGet(_)
+        | Expr::StrLen
+        | Expr::StrSum(_)
+        | Expr::StrSumBounded { .. }
+        | Expr::StrHash(_)
+        | Expr::TableGet(_, _)
+        | Expr::TableIndexMask(_)
+        | Expr::HashMask => true,
+        Expr::BinOp(BinOp::And, ref a, ref b) => in_u32_range(a) || in_u32_range(b),
+        // The shifted operand is masked before the shift.
+        Expr::BinOp(BinOp::Shrl, _, _) => true,
+        Expr::BinOp(BinOp::Add | BinOp::Sub | BinOp::Shll, _, _) => false,
+    }
+}
+
+// Masks the converted expression if it may be out of range.
+fn as_u32(expr: &Expr, e: CExpr) -> CExpr {
+    let x = CExprBuilder();
+    if in_u32_range(expr) {
+        e
+    } else {
+        x.and(e, x.var(U32_MASK))
+    }
+}
+
+impl Operands for PythonBackend {
+    fn str_get(&self, i: CExpr) -> CExpr {
+        CExprBuilder().index("key", i)
+    }
+
+    fn str_len(&self) -> CExpr {
+        CExprBuilder().var("len(key)")
+    }
+
+    fn str_sum(&self, mask: u32) -> CExpr {
+        let x = CExprBuilder();
+        x.call(format!("_str_sum_{mask}"), vec![x.var("key")])
+    }
+
+    fn str_sum_bounded(&self, mask: u32, head: u32, tail: u32) -> CExpr {
+        let x = CExprBuilder();
+        x.call(format!("_str_sum_{mask}_{head}_{tail}"), vec![x.var("key")])
+    }
+
+    fn str_hash(&self, seed: u32) -> CExpr {
+        let x = CExprBuilder();
+        x.call(format!("_str_hash_{seed}"), vec![x.var("key")])
+    }
+
+    fn table_get(&self, table: &str, i: CExpr) -> CExpr {
+        CExprBuilder().index(table, i)
+    }
+
+    fn index(&self, expr: &Expr, e: CExpr) -> CExpr {
+        as_u32(expr, e)
+    }
+
+    // Only the right shift moves high bits into the result.
+    fn shifted_right(&self, expr: &Expr, e: CExpr) -> CExpr {
+        as_u32(expr, e)
+    }
+}
+
+impl PythonBackend {
+    pub fn new(spec: Spec, phf: Phf) -> PythonBackend {
+        PythonBackend { spec, phf }
+    }
+
+    fn compile_str_sum(&self, lines: &mut Lines, mask: u32) {
+        let x = CExprBuilder();
+        let term = str_sum_term(self, mask, x.var("i"));
+        let term = term.display(&PYTHON);
+        lines.extend(&[
+            &format!("def _str_sum_{mask}(key: bytes) -> int:"),
+            &format!("\treturn sum({term} for i in range(len(key))) & {U32_MASK}"),
+        ]);
+    }
+
+    fn compile_str_sum_bounded(&self, lines: &mut Lines, mask: u32, head: u32, tail: u32) {
+        let x = CExprBuilder();
+        lines.push(&format!(
+            "def _str_sum_{mask}_{head}_{tail}(key: bytes) -> int:"
+        ));
+        let body_indent = lines.indent();
+        lines.push("n = len(key)");
+        lines.push("s = 0");
+        for (len_above, term) in str_sum_bounded_terms(self, x.var("n"), mask, head, tail) {
+            let term = term.display(&PYTHON);
+            lines.push(&format!("if n > {len_above}:"));
+            lines.push(&format!("\ts += {term}"));
+        }
+        lines.push(&format!("return s & {U32_MASK}"));
+        lines.dedent(body_indent);
+    }
+
+    fn compile_str_hash(&self, lines: &mut Lines, seed: u32) {
+        let x = CExprBuilder();
+        let symbol = self.str_get(x.var("i"));
+        let symbol = symbol.display(&PYTHON);
+        lines.extend(&[
+            &format!("def _str_hash_{seed}(key: bytes) -> int:"),
+            &format!("\th = {seed}"),
+            "\tfor i in range(len(key)):",
+            &format!("\t\th = (h + {symbol}) & {U32_MASK}"),
+            &format!("\t\th = (h + (h << 10)) & {U32_MASK}"),
+            "\t\th ^= h >> 6",
+            &format!("\th = (h + (h << 3)) & {U32_MASK}"),
+            "\th ^= h >> 11",
+            &format!("\treturn (h + (h << 15)) & {U32_MASK}"),
+        ]);
+    }
+
+    fn compile_keys(&self, lines: &mut Lines) {
+        let key_table = &self.phf.key_table;
+        let keys: Vec<String> = key_table
+            .iter()
+            .map(|key| format!("b{}", hex_escaped(key)))
+            .collect();
+        compile_tuple(lines, "_KEYS", &keys);
+        let values: Vec<String> = (0..key_table.len())
+            .map(|i| self.phf.ordinals[i].map_or(0, to_u32).to_string())
+            .collect();
+        compile_tuple(lines, "_VALUES", &values);
+    }
+
+    fn compile_hash(&self, lines: &mut Lines, tac: &Tac, table_refs: &[(String, u32)]) {
+        lines.push("def hash(key: bytes) -> int:");
+        let hash_indent = lines.indent();
+
+        let min = self.spec.min_interpreted_key_len;
+        let max = self.spec.max_interpreted_key_len;
+        let condition = if min == max {
+            format!("len(key) != {min}")
+        } else {
+            format!("not {min} <= len(key) <= {max}")
+        };
+        lines.push(&format!("if {condition}:"));
+        lines.push("\treturn 0");
+
+        let exprs = tac.unflatten_dag().0;
+        for (i, expr) in exprs.iter().enumerate() {
+            let expr = as_u32(expr, lower(self, &self.phf, table_refs, expr));
+            let expr_str = expr.display(&PYTHON);
+            if i == exprs.len() - 1 {
+                lines.push(&format!("return {expr_str}"));
+            } else {
+                lines.push(&format!("x{i} = {expr_str}"));
+            }
+        }
+
+        lines.dedent(hash_indent);
+    }
+
+    fn compile(&self, lines: &mut Lines) {
+        let phf = &self.phf;
+        let tac = &phf.tac;
+
+        lines.extend(&[
+            "# Code generated by secohash. DO NOT EDIT.",
+            "",
+            "from typing import Optional",
+            "",
+        ]);
+
+        self.compile_keys(lines);
+        let (arrays, mut table_refs) = pack_tables(&phf.tables);
+        for (_, name, data) in arrays {
+            let elements = data.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            compile_tuple(lines, &format!("_{}", name.to_uppercase()), &elements);
+        }
+        for (name, _) in table_refs.iter_mut() {
+            *name = format!("_{}", name.to_uppercase());
+        }
+
+        let helpers = Helpers::new(tac);
+        for mask in helpers.str_sums {
+            lines.extend(&["", ""]);
+            self.compile_str_sum(lines, mask);
+        }
+        for (mask, head, tail) in helpers.str_sums_bounded {
+            lines.extend(&["", ""]);
+            self.compile_str_sum_bounded(lines, mask, head, tail);
+        }
+        for seed in helpers.str_hashes {
+            lines.extend(&["", ""]);
+            self.compile_str_hash(lines, seed);
+        }
+
+        lines.extend(&["", ""]);
+        self.compile_hash(lines, tac, &table_refs);
+
+        // Unused slots hold either an empty key, or a real key that hashes
+        // elsewhere, so they can never match.
+        lines.extend(&[
+            "",
+            "",
+            "def lookup(key: bytes) -> Optional[int]:",
+            "\t\"\"\"Returns the index of key in the input, or None if it is absent.\"\"\"",
+            "\ti = hash(key)",
+            "\tif _KEYS[i] != key:",
+            "\t\treturn None",
+            "\treturn _VALUES[i]",
+        ]);
+    }
+
+    pub fn emit(&self) -> Vec<OutputFile> {
+        let mut lines = Lines::new(79, 4, true);
+        self.compile(&mut lines);
+        let mut lines: Vec<String> = lines.into();
+        for line in lines.iter_mut() {
+            line.push('\n');
+        }
+        vec![OutputFile {
+            extension: "py".into(),
+            contents: lines.join(""),
+        }]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backend::lower::test_hashes,
+        search::{search, SearchOptions},
+        util::to_usize,
+    };
+    use std::{
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    #[test]
+    fn test_in_u32_range() {
+        let key_byte = || Box::new(Expr::StrGet(Box::new(Expr::Imm(0))));
+        let shifted = Expr::BinOp(BinOp::Shll, key_byte(), Box::new(Expr::Imm(30)));
+        assert!(!in_u32_range(&shifted));
+        let masked = Expr::BinOp(
+            BinOp::And,
+            Box::new(shifted.clone()),
+            Box::new(Expr::HashMask),
+        );
+        assert!(in_u32_range(&masked));
+        let diff = Expr::BinOp(BinOp::Sub, key_byte(), key_byte());
+        assert!(!in_u32_range(&diff));
+    }
+
+    #[test]
+    fn test_emit() {
+        let keys: Vec<Vec<u32>> = ["if", "else", "while"]
+            .iter()
+            .map(|key| key.bytes().map(u32::from).collect())
+            .collect();
+        let spec = Spec::new(keys);
+        let phf = search(&spec, &SearchOptions::default()).unwrap();
+        let files = PythonBackend::new(spec, phf).emit();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension, "py");
+        let contents = &files[0].contents;
+        assert!(contents.contains("\ndef hash(key: bytes) -> int:\n"));
+        assert!(contents.contains("\ndef lookup(key: bytes) -> Optional[int]:\n"));
+        assert!(contents.contains("b\"while\""));
+    }
+
+    // Runs the emitted modules with python3 against Trace, if it is
+    // installed.
+    #[test]
+    fn test_run() {
+        let hashes = test_hashes();
+        assert!(hashes.len() >= 10);
+        for (spec, phf, slots) in hashes {
+            let mut cases = Vec::new();
+            for (key, &slot) in spec.interpreted_keys.iter().zip(&slots) {
+                let value = phf.ordinals[to_usize(slot)].unwrap();
+                cases.push(format!("    (b{}, {slot}, {value}),\n", hex_escaped(key)));
+            }
+            let module = &PythonBackend::new(spec, phf).emit()[0].contents;
+            let script = format!(
+                "{module}\n\
+                 \n\
+                 cases = [\n\
+                 {}\
+                 ]\n\
+                 for key, slot, value in cases:\n    \
+                     assert hash(key) == slot, key\n    \
+                     assert lookup(key) == value, key\n\
+                 assert lookup(b\"\\xff\" * 100) is None\n",
+                cases.concat()
+            );
+            let Ok(mut python) = Command::new("python3")
+                .arg("-")
+                .stdin(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+            else {
+                return;
+            };
+            python
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(script.as_bytes())
+                .unwrap();
+            let output = python.wait_with_output().unwrap();
+            assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+}
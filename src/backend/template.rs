@@ -0,0 +1,601 @@
+// A backend driven by a template, so that a target can be added without
+// touching the compiler.
+//
+// Templates use a subset of Mustache. {{name}} inserts a value, {{#name}}
+// ... {{/name}} repeats its contents for each item of a list, or renders them
+// once if a flag is true, and {{^name}} ... {{/name}} renders its contents if
+// a flag is false or a list is empty. {{! ...}} is a comment. A line holding
+// nothing but one section tag or comment is dropped from the output. Inside
+// a list, names are looked up in the item first, and every item has index,
+// first and last.
+//
+// Lines starting with %% are directives. "%% extension zig" names the
+// extension of the output, and the other directives set how the expressions
+// of the hash are spelled, such as "%% add {a} +% {b}" (see OPS and
+// DEFAULT_PATTERNS).
+//
+// The values are:
+//
+//   key_count, hash_mask, min_len, max_len
+//   fixed_len          whether min_len == max_len
+//   reads_key          whether the hash reads the key at all
+//   keys               the key table: str (a literal with \x escapes), c_str
+//                      (a C literal), len, value, and used (false for the
+//                      placeholders in unused slots)
+//   arrays             name, bits, len and rows of elements, each a value
+//   str_sums           mask, and term, the expr to add for the symbol at i
+//   str_sums_bounded   mask, head, tail, and terms: len_above and expr, to
+//                      be added if the length is above len_above
+//   str_hashes         seed of each one-at-a-time hash of the whole key, and
+//                      symbol, the expr of the symbol at i
+//   assignments        name and expr of each intermediate of the hash
+//   result             the expression of the hash
+
+use super::{
+    c_expr::{fill, CBinOp, CExpr, CExprBuilder, Syntax, C},
+    c_str_formatter::{hex_escaped, CStrFormatter},
+    lower::{lower, reads_key, str_sum_bounded_terms, str_sum_term, Helpers, Operands},
+    pack_tables, CBackend, OutputFile,
+};
+use crate::{ir::Expr, search::Phf, spec::Spec, util::to_u32};
+use std::collections::BTreeMap;
+
+// Templates built into the binary, which --template selects by name.
+const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("c", include_str!("templates/c.tmpl")),
+    ("zig", include_str!("templates/zig.tmpl")),
+];
+
+pub fn builtin_template(name: &str) -> Option<&'static str> {
+    BUILTIN_TEMPLATES
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|&(_, text)| text)
+}
+
+// The directive of each operator. Operators are spelled as in C unless a
+// directive says otherwise, and nested operations are always parenthesized.
+const OPS: &[(&str, CBinOp)] = &[
+    ("add", CBinOp::Add),
+    ("sub", CBinOp::Sub),
+    ("and", CBinOp::And),
+    ("shl", CBinOp::Shl),
+    ("shr", CBinOp::Shr),
+];
+
+// The pattern of each kind of operand. Symbols and table elements are widened
+// to uint32_t, since narrower values promote to int and overflow when shifted.
+const DEFAULT_PATTERNS: &[(&str, &str)] = &[
+    ("var", "x{n}"),
+    ("str_get", "(uint32_t) (unsigned char) key[{i}]"),
+    ("str_len", "len"),
+    ("str_sum", "str_sum_{mask}(key, len)"),
+    ("str_sum_bounded", "str_sum_{mask}_{head}_{tail}(key, len)"),
+    ("str_hash", "str_hash_{seed}(key, len)"),
+    ("table_get", "(uint32_t) {table}[{i}]"),
+];
+
+// The most columns that a row of array elements takes.
+const ROW_COLS: usize = 76;
+
+enum Value {
+    Str(String),
+    Bool(bool),
+    List(Vec<Context>),
+}
+
+type Context = BTreeMap<&'static str, Value>;
+
+fn str<T: ToString>(value: T) -> Value {
+    Value::Str(value.to_string())
+}
+
+fn list(mut items: Vec<Context>) -> Value {
+    let len = items.len();
+    for (i, item) in items.iter_mut().enumerate() {
+        item.insert("index", str(i));
+        item.insert("first", Value::Bool(i == 0));
+        item.insert("last", Value::Bool(i == len - 1));
+    }
+    Value::List(items)
+}
+
+// Splits the elements into rows that fit in ROW_COLS when written with a
+// comma after each.
+fn rows(elements: Vec<String>) -> Value {
+    let mut rows = Vec::new();
+    let mut row: Vec<Context> = Vec::new();
+    let mut cols = 0;
+    for element in elements {
+        let width = element.len() + 2;
+        if !row.is_empty() && cols + width > ROW_COLS + 1 {
+            rows.push(Context::from([("elements", list(row))]));
+            row = Vec::new();
+            cols = 0;
+        }
+        cols += width;
+        row.push(Context::from([("value", Value::Str(element))]));
+    }
+    if !row.is_empty() {
+        rows.push(Context::from([("elements", list(row))]));
+    }
+    list(rows)
+}
+
+enum Token {
+    Text(String),
+    Var(String),
+    Open(String, bool),
+    Close(String),
+    Comment,
+}
+
+enum Node {
+    Text(String),
+    Var(String),
+    Section {
+        name: String,
+        inverted: bool,
+        nodes: Vec<Node>,
+    },
+}
+
+pub struct Template {
+    extension: String,
+    syntax: Syntax,
+    patterns: BTreeMap<&'static str, String>,
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Template, String> {
+        let mut extension = None;
+        let mut syntax = C.clone();
+        let mut patterns: BTreeMap<&'static str, String> = DEFAULT_PATTERNS
+            .iter()
+            .map(|&(name, pattern)| (name, pattern.to_string()))
+            .collect();
+        let mut tokens = Vec::new();
+        for (i, line) in text.split_inclusive('\n').enumerate() {
+            if let Some(directive) = line.strip_prefix("%%") {
+                let directive = directive.trim();
+                let (name, value) = directive.split_once(' ').unwrap_or((directive, ""));
+                let value = value.trim().to_string();
+                if name == "extension" {
+                    extension = Some(value);
+                } else if let Some(&(_, op)) = OPS.iter().find(|(op_name, _)| *op_name == name) {
+                    syntax.set_op(op, value);
+                } else if let Some(pattern) = patterns.get_mut(name) {
+                    *pattern = value;
+                } else {
+                    return Err(format!("line {}: unknown directive {name:?}", i + 1));
+                }
+                continue;
+            }
+            let line_tokens =
+                Self::tokenize(line).map_err(|err| format!("line {}: {err}", i + 1))?;
+            tokens.extend(Self::strip_standalone(line_tokens));
+        }
+        let Some(extension) = extension else {
+            return Err("missing %% extension directive".into());
+        };
+        let nodes = Self::build(&mut tokens.into_iter(), None)?;
+        Ok(Template {
+            extension,
+            syntax,
+            patterns,
+            nodes,
+        })
+    }
+
+    fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+        let mut tokens = Vec::new();
+        let mut rest = line;
+        while let Some(mut start) = rest.find("{{") {
+            // Braces before a tag are literal, as in "{{{c_str}}, ...}".
+            while rest[start + 2..].starts_with('{') {
+                start += 1;
+            }
+            let Some(len) = rest[start + 2..].find("}}") else {
+                return Err("unclosed tag".into());
+            };
+            if start > 0 {
+                tokens.push(Token::Text(rest[..start].into()));
+            }
+            let tag = rest[start + 2..start + 2 + len].trim();
+            let name = |prefix: usize| tag[prefix..].trim().to_string();
+            tokens.push(match tag.chars().next() {
+                None => return Err("empty tag".into()),
+                Some('#') => Token::Open(name(1), false),
+                Some('^') => Token::Open(name(1), true),
+                Some('/') => Token::Close(name(1)),
+                Some('!') => Token::Comment,
+                Some(_) => Token::Var(name(0)),
+            });
+            rest = &rest[start + 2 + len + 2..];
+        }
+        if !rest.is_empty() {
+            tokens.push(Token::Text(rest.into()));
+        }
+        Ok(tokens)
+    }
+
+    // Drops the rest of a line holding only one section tag or comment.
+    fn strip_standalone(tokens: Vec<Token>) -> Vec<Token> {
+        let mut tags = tokens.iter().filter(|t| !matches!(t, Token::Text(_)));
+        let standalone = matches!(
+            (tags.next(), tags.next()),
+            (
+                Some(Token::Open(_, _) | Token::Close(_) | Token::Comment),
+                None
+            )
+        ) && tokens.iter().all(|t| match t {
+            Token::Text(text) => text.trim().is_empty(),
+            _ => true,
+        });
+        if standalone {
+            tokens
+                .into_iter()
+                .filter(|t| !matches!(t, Token::Text(_)))
+                .collect()
+        } else {
+            tokens
+        }
+    }
+
+    fn build(
+        tokens: &mut std::vec::IntoIter<Token>,
+        open: Option<&str>,
+    ) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        while let Some(token) = tokens.next() {
+            match token {
+                Token::Text(text) => nodes.push(Node::Text(text)),
+                Token::Var(name) => nodes.push(Node::Var(name)),
+                Token::Comment => (),
+                Token::Open(name, inverted) => {
+                    let section_nodes = Self::build(tokens, Some(&name))?;
+                    nodes.push(Node::Section {
+                        name,
+                        inverted,
+                        nodes: section_nodes,
+                    });
+                }
+                Token::Close(name) if open == Some(name.as_str()) => return Ok(nodes),
+                Token::Close(name) => return Err(format!("unexpected {{{{/{name}}}}}")),
+            }
+        }
+        match open {
+            Some(name) => Err(format!("unclosed section {name:?}")),
+            None => Ok(nodes),
+        }
+    }
+
+    fn render(nodes: &[Node], stack: &mut Vec<&Context>, out: &mut String) -> Result<(), String> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Var(name) => match Self::lookup(stack, name)? {
+                    Value::Str(s) => out.push_str(s),
+                    Value::Bool(b) => out.push_str(&b.to_string()),
+                    Value::List(_) => return Err(format!("{name:?} is a list")),
+                },
+                Node::Section {
+                    name,
+                    inverted,
+                    nodes,
+                } => match (Self::lookup(stack, name)?, inverted) {
+                    (Value::List(items), false) => {
+                        for item in items {
+                            stack.push(item);
+                            Self::render(nodes, stack, out)?;
+                            stack.pop();
+                        }
+                    }
+                    (Value::List(items), true) if items.is_empty() => {
+                        Self::render(nodes, stack, out)?
+                    }
+                    (Value::Bool(b), _) if b != inverted => Self::render(nodes, stack, out)?,
+                    (Value::Str(s), _) if s.is_empty() == *inverted => {
+                        Self::render(nodes, stack, out)?
+                    }
+                    _ => (),
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn lookup<'a>(stack: &[&'a Context], name: &str) -> Result<&'a Value, String> {
+        stack
+            .iter()
+            .rev()
+            .find_map(|context| context.get(name))
+            .ok_or_else(|| format!("unknown name {name:?}"))
+    }
+}
+
+// Operands are spelled by filling in their patterns, and operators are left to
+// the syntax.
+impl Template {
+    fn operand(&self, name: &str, args: &[(&str, &str)]) -> CExpr {
+        CExprBuilder().var(fill(&self.patterns[name], args))
+    }
+
+    pub fn print(&self, e: &CExpr) -> String {
+        e.display(&self.syntax).to_string()
+    }
+}
+
+impl Operands for Template {
+    fn var(&self, n: usize) -> CExpr {
+        self.operand("var", &[("n", &n.to_string())])
+    }
+
+    fn str_get(&self, i: CExpr) -> CExpr {
+        self.operand("str_get", &[("i", &self.print(&i))])
+    }
+
+    fn str_len(&self) -> CExpr {
+        self.operand("str_len", &[])
+    }
+
+    fn str_sum(&self, mask: u32) -> CExpr {
+        self.operand("str_sum", &[("mask", &mask.to_string())])
+    }
+
+    fn str_sum_bounded(&self, mask: u32, head: u32, tail: u32) -> CExpr {
+        self.operand(
+            "str_sum_bounded",
+            &[
+                ("mask", &mask.to_string()),
+                ("head", &head.to_string()),
+                ("tail", &tail.to_string()),
+            ],
+        )
+    }
+
+    fn str_hash(&self, seed: u32) -> CExpr {
+        self.operand("str_hash", &[("seed", &seed.to_string())])
+    }
+
+    fn table_get(&self, table: &str, i: CExpr) -> CExpr {
+        self.operand("table_get", &[("table", table), ("i", &self.print(&i))])
+    }
+}
+
+pub struct TemplateBackend {
+    spec: Spec,
+    phf: Phf,
+    template: Template,
+}
+
+impl TemplateBackend {
+    pub fn new(spec: Spec, phf: Phf, template: Template) -> TemplateBackend {
+        TemplateBackend {
+            spec,
+            phf,
+            template,
+        }
+    }
+
+    fn print_expr(&self, expr: &Expr, table_refs: &[(String, u32)]) -> String {
+        let template = &self.template;
+        template.print(&lower(template, &self.phf, table_refs, expr))
+    }
+
+    fn context(&self) -> Context {
+        let spec = &self.spec;
+        let phf = &self.phf;
+
+        let tac = &phf.tac;
+        let (arrays, table_refs) = pack_tables(&phf.tables);
+
+        let mut context = Context::new();
+        context.insert("key_count", str(phf.key_table.len()));
+        context.insert("hash_mask", str(phf.key_table.len() - 1));
+        context.insert("min_len", str(spec.min_interpreted_key_len));
+        context.insert("max_len", str(spec.max_interpreted_key_len));
+        context.insert(
+            "fixed_len",
+            Value::Bool(spec.min_interpreted_key_len == spec.max_interpreted_key_len),
+        );
+        context.insert("reads_key", Value::Bool(reads_key(tac)));
+
+        let str_formatter = CStrFormatter::new();
+        let keys = phf
+            .key_table
+            .iter()
+            .enumerate()
+            .map(|(i, key)| {
+                let value = phf.ordinals[i];
+                Context::from([
+                    ("str", Value::Str(hex_escaped(key))),
+                    (
+                        "c_str",
+                        Value::Str(str_formatter.format(CBackend::key_bytes(key))),
+                    ),
+                    ("len", str(key.len())),
+                    ("value", str(value.map_or(0, to_u32))),
+                    ("used", Value::Bool(value.is_some())),
+                ])
+            })
+            .collect();
+        context.insert("keys", list(keys));
+
+        let arrays = arrays
+            .into_iter()
+            .map(|(bits, name, data)| {
+                Context::from([
+                    ("name", Value::Str(name)),
+                    ("bits", str(bits)),
+                    ("len", str(data.len())),
+                    ("rows", rows(data.iter().map(|x| x.to_string()).collect())),
+                ])
+            })
+            .collect();
+        context.insert("arrays", list(arrays));
+
+        let template = &self.template;
+        let x = CExprBuilder();
+        let helpers = Helpers::new(tac);
+        let str_sums = helpers
+            .str_sums
+            .into_iter()
+            .map(|mask| {
+                let term = str_sum_term(template, mask, x.var("i"));
+                Context::from([("mask", str(mask)), ("term", str(template.print(&term)))])
+            })
+            .collect();
+        context.insert("str_sums", list(str_sums));
+        let str_sums_bounded = helpers
+            .str_sums_bounded
+            .into_iter()
+            .map(|(mask, head, tail)| {
+                let terms = str_sum_bounded_terms(template, template.str_len(), mask, head, tail)
+                    .into_iter()
+                    .map(|(len_above, term)| {
+                        Context::from([
+                            ("len_above", str(len_above)),
+                            ("expr", str(template.print(&term))),
+                        ])
+                    })
+                    .collect();
+                Context::from([
+                    ("mask", str(mask)),
+                    ("head", str(head)),
+                    ("tail", str(tail)),
+                    ("terms", list(terms)),
+                ])
+            })
+            .collect();
+        context.insert("str_sums_bounded", list(str_sums_bounded));
+        let symbol = template.print(&template.str_get(x.var("i")));
+        let str_hashes = helpers
+            .str_hashes
+            .into_iter()
+            .map(|seed| Context::from([("seed", str(seed)), ("symbol", str(&symbol))]))
+            .collect();
+        context.insert("str_hashes", list(str_hashes));
+
+        let mut exprs = tac.unflatten_dag().0;
+        let result = exprs.pop().unwrap();
+        let assignments = exprs
+            .iter()
+            .enumerate()
+            .map(|(i, expr)| {
+                Context::from([
+                    ("name", str(template.print(&template.var(i)))),
+                    ("expr", Value::Str(self.print_expr(expr, &table_refs))),
+                ])
+            })
+            .collect();
+        context.insert("assignments", list(assignments));
+        context.insert("result", Value::Str(self.print_expr(&result, &table_refs)));
+        context
+    }
+
+    pub fn emit(&self) -> Result<Vec<OutputFile>, String> {
+        let context = self.context();
+        let mut contents = String::new();
+        Template::render(&self.template.nodes, &mut vec![&context], &mut contents)?;
+        Ok(vec![OutputFile {
+            extension: self.template.extension.clone(),
+            contents,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backend::test::{dataset_keys, run_with_ubsan},
+        search::{search, SearchOptions},
+    };
+
+    fn render(text: &str, context: &Context) -> Result<String, String> {
+        let template = Template::parse(text)?;
+        let mut out = String::new();
+        Template::render(&template.nodes, &mut vec![context], &mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_render() {
+        let items = vec![
+            Context::from([("name", str("a"))]),
+            Context::from([("name", str("b"))]),
+        ];
+        let context = Context::from([
+            ("items", list(items)),
+            ("empty", list(vec![])),
+            ("title", str("t")),
+        ]);
+        let text = "%% extension txt\n\
+                    {{title}}: {{#items}}{{name}}{{^last}}, {{/last}}{{/items}}\n\
+                    {{#items}}\n\
+                    \x20 {{index}} {{{name}}} {{title}}\n\
+                    {{/items}}\n\
+                    {{! comment }}\n\
+                    {{^empty}}\n\
+                    none\n\
+                    {{/empty}}\n";
+        assert_eq!(
+            render(text, &context).unwrap(),
+            "t: a, b\n  0 {a} t\n  1 {b} t\nnone\n"
+        );
+    }
+
+    #[test]
+    fn test_render_errors() {
+        let context = Context::from([("x", list(vec![]))]);
+        let check = |text: &str| render(&format!("%% extension txt\n{text}"), &context).err();
+        assert_eq!(check("{{y}}"), Some("unknown name \"y\"".into()));
+        assert_eq!(check("{{x}}"), Some("\"x\" is a list".into()));
+        assert_eq!(check("{{#x}}"), Some("unclosed section \"x\"".into()));
+        assert_eq!(check("{{/x}}"), Some("unexpected {{/x}}".into()));
+        assert_eq!(check("{{x"), Some("line 2: unclosed tag".into()));
+        assert_eq!(
+            check("%% mul {a} * {b}"),
+            Some("line 2: unknown directive \"mul\"".into())
+        );
+        assert_eq!(
+            render("{{#x}}{{/x}}", &context).err(),
+            Some("missing %% extension directive".into())
+        );
+    }
+
+    #[test]
+    fn test_builtin_templates() {
+        for &(name, text) in BUILTIN_TEMPLATES {
+            let keys: Vec<Vec<u32>> = ["if", "else", "while"]
+                .iter()
+                .map(|key| key.bytes().map(u32::from).collect())
+                .collect();
+            let spec = Spec::new(keys);
+            let phf = search(&spec, &SearchOptions::default()).unwrap();
+            let template = Template::parse(text).unwrap();
+            let files = TemplateBackend::new(spec, phf, template).emit().unwrap();
+            assert_eq!(files[0].extension, name);
+            assert!(files[0].contents.contains("\"while\""));
+        }
+    }
+
+    #[test]
+    fn test_c_wide_shifts_are_defined() {
+        // This hash shifts a symbol by 26, which overflows unless the symbol
+        // is widened first.
+        let keys = dataset_keys(include_str!("../../benchmarks/datasets/usa_states.txt"));
+        let options = SearchOptions {
+            seed: Some(7),
+            ..SearchOptions::default()
+        };
+        let spec = Spec::new(keys.clone());
+        let phf = search(&spec, &options).unwrap();
+        let template = Template::parse(builtin_template("c").unwrap()).unwrap();
+        let files = TemplateBackend::new(spec, phf, template).emit().unwrap();
+        assert!(files[0].contents.contains("<< 26"));
+        run_with_ubsan(&files[0].contents, &keys);
+    }
+}
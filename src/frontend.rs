@@ -1,3 +1,4 @@
+pub mod args;
 mod delimited;
 mod pos;
-mod spec;
+pub mod spec;
@@ -0,0 +1,494 @@
+use super::spec::Target;
+use crate::{
+    backend::{
+        CBackendConfig, CStandard, CppBackendConfig, GoBackendConfig, IntTypes, KeyAccess,
+        KeyLayout, OutputStyle, StrSumLowering,
+    },
+    search::{CostModel, SearchOptions, SelectorFamilies, Strategy, MAX_SUM_BOUND, MAX_SUM_MASKS},
+};
+use std::{fmt::Display, str::FromStr};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputStyle {
+    // One key per line.
+    #[default]
+    Keys,
+    // One key per line, followed by a tab and how often it is looked up.
+    Weighted,
+}
+
+#[derive(Default)]
+pub struct Args {
+    pub input_style: InputStyle,
+    pub target: Target,
+    pub search_options: SearchOptions,
+    pub c_backend_config: CBackendConfig,
+    pub cpp_backend_config: CppBackendConfig,
+    pub go_backend_config: GoBackendConfig,
+    // The name of a built-in template or the path of one, for
+    // --target=template.
+    pub template: Option<String>,
+    // Where to write the output, without an extension. None for stdout.
+    pub output: Option<String>,
+}
+
+fn parse_choice<T: Copy>(name: &str, value: &str, choices: &[(&str, T)]) -> Result<T, String> {
+    match choices.iter().find(|(choice, _)| *choice == value) {
+        Some(&(_, parsed)) => Ok(parsed),
+        None => Err(format!(
+            "invalid value {:?} for {}: expected one of {}",
+            value,
+            name,
+            choices
+                .iter()
+                .map(|(choice, _)| *choice)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+fn parse_number<T>(name: &str, value: &str, max: T) -> Result<T, String>
+where
+    T: Display + FromStr + PartialOrd,
+{
+    match value.parse() {
+        Ok(n) if n <= max => Ok(n),
+        _ => Err(format!(
+            "invalid value {value:?} for {name}: expected a number up to {max}"
+        )),
+    }
+}
+
+fn parse_identifier(name: &str, value: &str) -> Result<String, String> {
+    let valid = value
+        .chars()
+        .enumerate()
+        .all(|(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+    if !valid {
+        return Err(format!(
+            "invalid value {value:?} for {name}: expected a C identifier"
+        ));
+    }
+    Ok(value.into())
+}
+
+// A C type name such as "unsigned long".
+fn parse_type(name: &str, value: &str) -> Result<String, String> {
+    let words: Vec<&str> = value.split(' ').collect();
+    for word in &words {
+        if word.is_empty() {
+            return Err(format!(
+                "invalid value {value:?} for {name}: expected a C type name"
+            ));
+        }
+        parse_identifier(name, word)?;
+    }
+    Ok(value.into())
+}
+
+// A C++ namespace, possibly nested.
+fn parse_namespace(name: &str, value: &str) -> Result<String, String> {
+    for part in value.split("::") {
+        if part.is_empty() {
+            return Err(format!(
+                "invalid value {value:?} for {name}: expected a C++ namespace"
+            ));
+        }
+        parse_identifier(name, part)?;
+    }
+    Ok(value.into())
+}
+
+fn parse_families(name: &str, value: &str) -> Result<SelectorFamilies, String> {
+    let mut families = SelectorFamilies {
+        len: false,
+        index: false,
+        arith: false,
+        table: false,
+        sum: false,
+        hash: false,
+    };
+    for family in value.split(',') {
+        let allowed = match family {
+            "len" => &mut families.len,
+            "index" => &mut families.index,
+            "arith" => &mut families.arith,
+            "table" => &mut families.table,
+            "sum" => &mut families.sum,
+            "hash" => &mut families.hash,
+            _ => {
+                return Err(format!(
+                    "invalid value {family:?} for {name}: expected a list of len, index, arith, table, sum, hash"
+                ))
+            }
+        };
+        *allowed = true;
+    }
+    Ok(families)
+}
+
+pub fn parse_args<I>(args: I) -> Result<Args, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut parsed = Args::default();
+    for arg in args {
+        let Some((name, value)) = arg.split_once('=') else {
+            return Err(format!(
+                "expected an option of the form --name=value: {arg:?}"
+            ));
+        };
+        match name {
+            "--input-style" => {
+                parsed.input_style = parse_choice(
+                    name,
+                    value,
+                    &[
+                        ("keys", InputStyle::Keys),
+                        ("weighted", InputStyle::Weighted),
+                    ],
+                )?;
+            }
+            "--target" => {
+                parsed.target = parse_choice(
+                    name,
+                    value,
+                    &[
+                        ("c", Target::C),
+                        ("cpp", Target::Cpp),
+                        ("go", Target::Go),
+                        ("js", Target::JavaScript),
+                        ("python", Target::Python),
+                        ("template", Target::Template),
+                        ("ts", Target::TypeScript),
+                    ],
+                )?;
+            }
+            "--namespace" => {
+                parsed.cpp_backend_config.namespace = parse_namespace(name, value)?;
+            }
+            "--template" => {
+                if value.is_empty() {
+                    return Err(format!(
+                        "invalid value \"\" for {name}: expected a template name or path"
+                    ));
+                }
+                parsed.template = Some(value.into());
+            }
+            "--package" => {
+                if value.is_empty() {
+                    return Err(format!(
+                        "invalid value \"\" for {name}: expected a Go package name"
+                    ));
+                }
+                parsed.go_backend_config.package = parse_identifier(name, value)?;
+            }
+            "--optimize" => {
+                parsed.search_options.cost_model = parse_choice(
+                    name,
+                    value,
+                    &[
+                        ("first", None),
+                        ("smallest", Some(CostModel::Smallest)),
+                        ("fastest", Some(CostModel::Fastest)),
+                    ],
+                )?;
+            }
+            "--strategy" => {
+                parsed.search_options.strategy = parse_choice(
+                    name,
+                    value,
+                    &[
+                        ("auto", Strategy::Auto),
+                        ("selectors", Strategy::Selectors),
+                        ("displacement", Strategy::Displacement),
+                    ],
+                )?;
+            }
+            "--max-instrs" => {
+                parsed.search_options.max_instrs = Some(parse_number(name, value, u64::MAX)?);
+            }
+            "--seed" => {
+                parsed.search_options.seed = Some(parse_number(name, value, u64::MAX)?);
+            }
+            "--search-exponent" => {
+                parsed.search_options.selector_limits.search_exponent =
+                    parse_number(name, value, 8)?;
+            }
+            "--pos-limit" => {
+                parsed.search_options.selector_limits.pos_limit =
+                    parse_number(name, value, usize::MAX)?;
+            }
+            "--sum-masks" => {
+                parsed.search_options.selector_limits.sum_masks =
+                    parse_number(name, value, MAX_SUM_MASKS)?;
+            }
+            "--sum-bound" => {
+                parsed.search_options.selector_limits.sum_bound =
+                    Some(parse_number(name, value, MAX_SUM_BOUND)?);
+            }
+            "--selectors" => {
+                parsed.search_options.selector_limits.families = parse_families(name, value)?;
+            }
+            "--key-access" => {
+                parsed.c_backend_config.key_access = parse_choice(
+                    name,
+                    value,
+                    &[
+                        ("contiguous", KeyAccess::Contiguous),
+                        ("callback", KeyAccess::Callback),
+                    ],
+                )?;
+            }
+            "--key-layout" => {
+                parsed.c_backend_config.key_layout = parse_choice(
+                    name,
+                    value,
+                    &[
+                        ("auto", KeyLayout::Auto),
+                        ("entries", KeyLayout::Entries),
+                        ("pool", KeyLayout::Pool),
+                        ("inline", KeyLayout::Inline),
+                    ],
+                )?;
+            }
+            "--output-style" => {
+                parsed.c_backend_config.output_style = parse_choice(
+                    name,
+                    value,
+                    &[
+                        ("source", OutputStyle::Source),
+                        ("split", OutputStyle::Split),
+                        ("header", OutputStyle::Header),
+                    ],
+                )?;
+            }
+            "--prefix" => {
+                parsed.c_backend_config.prefix = parse_identifier(name, value)?;
+            }
+            "--str-sum" => {
+                parsed.c_backend_config.str_sum_lowering = parse_choice(
+                    name,
+                    value,
+                    &[
+                        ("unrolled", StrSumLowering::Unrolled),
+                        ("vectorized", StrSumLowering::Vectorized),
+                    ],
+                )?;
+            }
+            "--c-standard" => {
+                parsed.c_backend_config.standard = parse_choice(
+                    name,
+                    value,
+                    &[("c89", CStandard::C89), ("c99", CStandard::C99)],
+                )?;
+            }
+            "--uint8-type" | "--uint16-type" | "--uint32-type" | "--uint64-type" => {
+                let int_types = parsed
+                    .c_backend_config
+                    .int_types
+                    .get_or_insert_with(IntTypes::default);
+                let int_type = match name {
+                    "--uint8-type" => &mut int_types.u8,
+                    "--uint16-type" => &mut int_types.u16,
+                    "--uint32-type" => &mut int_types.u32,
+                    _ => &mut int_types.u64,
+                };
+                *int_type = parse_type(name, value)?;
+            }
+            "--output" => {
+                let file_name = value.rsplit('/').next().unwrap();
+                if file_name.is_empty() {
+                    return Err(format!(
+                        "invalid value {value:?} for {name}: expected a file path"
+                    ));
+                }
+                parsed.c_backend_config.header_name = format!("{file_name}.h");
+                parsed.output = Some(value.into());
+            }
+            _ => return Err(format!("unknown option {name:?}")),
+        }
+    }
+    if parsed.target == Target::C
+        && parsed.c_backend_config.output_style == OutputStyle::Split
+        && parsed.output.is_none()
+    {
+        return Err("--output-style=split writes two files and needs --output".into());
+    }
+    if (parsed.target == Target::Template) != parsed.template.is_some() {
+        return Err("--target=template and --template go together".into());
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|&arg| arg.into()))
+    }
+
+    #[test]
+    fn test_parse_args_empty() {
+        let args = parse(&[]).unwrap();
+        assert_eq!(args.input_style, InputStyle::Keys);
+        assert_eq!(args.search_options.cost_model, None);
+        assert_eq!(args.search_options.strategy, Strategy::Auto);
+        assert_eq!(args.search_options.seed, None);
+        assert_eq!(args.c_backend_config.key_layout, KeyLayout::Entries);
+        assert_eq!(args.c_backend_config.output_style, OutputStyle::Source);
+        assert_eq!(args.output, None);
+        assert_eq!(args.target, Target::C);
+    }
+
+    #[test]
+    fn test_parse_args_choices() {
+        let args = parse(&[
+            "--key-layout=pool",
+            "--input-style=weighted",
+            "--optimize=fastest",
+            "--strategy=displacement",
+            "--key-access=callback",
+            "--str-sum=vectorized",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.c_backend_config.str_sum_lowering,
+            StrSumLowering::Vectorized
+        );
+        assert_eq!(args.c_backend_config.key_layout, KeyLayout::Pool);
+        assert_eq!(args.c_backend_config.key_access, KeyAccess::Callback);
+        assert_eq!(args.input_style, InputStyle::Weighted);
+        assert_eq!(args.search_options.cost_model, Some(CostModel::Fastest));
+        assert_eq!(args.search_options.strategy, Strategy::Displacement);
+    }
+
+    #[test]
+    fn test_parse_args_limits() {
+        let args = parse(&[
+            "--search-exponent=2",
+            "--pos-limit=200",
+            "--sum-masks=4",
+            "--sum-bound=8",
+            "--selectors=index,len,arith",
+            "--seed=42",
+            "--max-instrs=100",
+        ])
+        .unwrap();
+        assert_eq!(args.search_options.seed, Some(42));
+        assert_eq!(args.search_options.max_instrs, Some(100));
+        let limits = args.search_options.selector_limits;
+        assert_eq!(limits.search_exponent, 2);
+        assert_eq!(limits.pos_limit, 200);
+        assert_eq!(limits.sum_masks, 4);
+        assert_eq!(limits.sum_bound, Some(8));
+        assert_eq!(
+            limits.families,
+            SelectorFamilies {
+                len: true,
+                index: true,
+                arith: true,
+                table: false,
+                sum: false,
+                hash: false,
+            }
+        );
+        assert_eq!(
+            parse(&["--sum-masks=33"]).err(),
+            Some("invalid value \"33\" for --sum-masks: expected a number up to 32".into())
+        );
+        assert_eq!(
+            parse(&["--selectors=index,loop"]).err(),
+            Some("invalid value \"loop\" for --selectors: expected a list of len, index, arith, table, sum, hash".into())
+        );
+    }
+
+    #[test]
+    fn test_parse_args_output() {
+        let args = parse(&[
+            "--output-style=split",
+            "--prefix=kw_",
+            "--output=gen/keywords",
+        ])
+        .unwrap();
+        assert_eq!(args.c_backend_config.output_style, OutputStyle::Split);
+        assert_eq!(args.c_backend_config.prefix, "kw_");
+        assert_eq!(args.c_backend_config.header_name, "keywords.h");
+        assert_eq!(args.output.as_deref(), Some("gen/keywords"));
+        assert_eq!(
+            parse(&["--output-style=split"]).err(),
+            Some("--output-style=split writes two files and needs --output".into())
+        );
+        assert_eq!(
+            parse(&["--prefix=1st"]).err(),
+            Some("invalid value \"1st\" for --prefix: expected a C identifier".into())
+        );
+    }
+
+    #[test]
+    fn test_parse_args_portability() {
+        let args = parse(&["--c-standard=c89", "--uint32-type=unsigned long"]).unwrap();
+        assert_eq!(args.c_backend_config.standard, CStandard::C89);
+        assert_eq!(
+            args.c_backend_config.int_types,
+            Some(IntTypes {
+                u32: "unsigned long".into(),
+                ..IntTypes::default()
+            })
+        );
+        assert_eq!(parse(&[]).unwrap().c_backend_config.int_types, None);
+        assert_eq!(
+            parse(&["--uint8-type=unsigned  char"]).err(),
+            Some(
+                "invalid value \"unsigned  char\" for --uint8-type: expected a C type name".into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_args_target() {
+        let args = parse(&["--target=cpp", "--namespace=lang::keywords"]).unwrap();
+        assert_eq!(args.target, Target::Cpp);
+        assert_eq!(args.cpp_backend_config.namespace, "lang::keywords");
+        assert_eq!(
+            parse(&["--namespace=lang::"]).err(),
+            Some("invalid value \"lang::\" for --namespace: expected a C++ namespace".into())
+        );
+        let args = parse(&["--target=template", "--template=zig"]).unwrap();
+        assert_eq!(args.target, Target::Template);
+        assert_eq!(args.template.as_deref(), Some("zig"));
+        assert_eq!(
+            parse(&["--template=zig"]).err(),
+            Some("--target=template and --template go together".into())
+        );
+        let args = parse(&["--target=go", "--package=keywords"]).unwrap();
+        assert_eq!(args.target, Target::Go);
+        assert_eq!(args.go_backend_config.package, "keywords");
+        assert_eq!(
+            parse(&["--package="]).err(),
+            Some("invalid value \"\" for --package: expected a Go package name".into())
+        );
+    }
+
+    #[test]
+    fn test_parse_args_invalid_choice() {
+        assert_eq!(
+            parse(&["--key-layout=foo"]).err(),
+            Some("invalid value \"foo\" for --key-layout: expected one of auto, entries, pool, inline".into())
+        );
+    }
+
+    #[test]
+    fn test_parse_args_unknown() {
+        assert_eq!(
+            parse(&["--foo=bar"]).err(),
+            Some("unknown option \"--foo\"".into())
+        );
+        assert_eq!(
+            parse(&["--foo"]).err(),
+            Some("expected an option of the form --name=value: \"--foo\"".into())
+        );
+    }
+}
@@ -1,7 +1,15 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum Target {
+    #[default]
     C,
+    Cpp,
+    Go,
     Java,
+    JavaScript,
+    Python,
     Rust,
+    Template,
+    TypeScript,
 }
 
 //                          valid key   invalid key
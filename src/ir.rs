@@ -1,6 +1,8 @@
 mod bin_op;
 mod expr;
 mod optimizer;
+mod positions;
+mod ranges;
 mod tables;
 mod tac;
 mod trace;
@@ -8,6 +10,8 @@ mod trace;
 pub use bin_op::*;
 pub use expr::*;
 pub use optimizer::*;
+pub use positions::*;
+pub use ranges::*;
 pub use tables::*;
 pub use tac::*;
 pub use trace::*;
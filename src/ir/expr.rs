@@ -13,6 +13,8 @@ pub enum Expr {
     StrGet(Box<Expr>),
     StrLen,
     StrSum(u32),
+    StrSumBounded { mask: u32, head: u32, tail: u32 },
+    StrHash(u32),
     TableGet(Table, Box<Expr>),
     TableIndexMask(Table),
     HashMask,
@@ -31,6 +33,8 @@ impl Expr {
             | Expr::Imm(_)
             | Expr::StrLen
             | Expr::StrSum(_)
+            | Expr::StrSumBounded { .. }
+            | Expr::StrHash(_)
             | Expr::TableIndexMask(_)
             | Expr::HashMask => self,
             Expr::StrGet(e) => x.str_get(e.transform(f)),
@@ -40,6 +44,26 @@ impl Expr {
         f(tmp)
     }
 
+    pub fn into_sum_terms(self, terms: &mut Vec<Expr>) {
+        match self {
+            Expr::BinOp(BinOp::Add, a, b) => {
+                a.into_sum_terms(terms);
+                b.into_sum_terms(terms);
+            }
+            _ => terms.push(self),
+        }
+    }
+
+    pub fn into_shifted(self) -> (Expr, u32) {
+        match self {
+            Expr::BinOp(BinOp::Shll, a, b) => match *b {
+                Expr::Imm(k) => (*a, k),
+                b => (ExprBuilder().shll(*a, b), 0),
+            },
+            _ => (self, 0),
+        }
+    }
+
     pub fn flatten(&self, tac: &mut Tac, var_to_reg: &HashMap<Var, Reg>) -> Reg {
         match *self {
             Expr::Imm(n) => tac.push(Instr::Imm(n)),
@@ -51,6 +75,10 @@ impl Expr {
             }
             Expr::StrLen => tac.push(Instr::StrLen),
             Expr::StrSum(m) => tac.push(Instr::StrSum(m)),
+            Expr::StrSumBounded { mask, head, tail } => {
+                tac.push(Instr::StrSumBounded { mask, head, tail })
+            }
+            Expr::StrHash(seed) => tac.push(Instr::StrHash(seed)),
             Expr::TableGet(t, ref i) => {
                 let i = i.flatten(tac, var_to_reg);
                 tac.push(Instr::TableGet(t, i))
@@ -89,6 +117,14 @@ impl ExprBuilder {
         Expr::StrSum(m)
     }
 
+    pub fn str_sum_bounded(&self, mask: u32, head: u32, tail: u32) -> Expr {
+        Expr::StrSumBounded { mask, head, tail }
+    }
+
+    pub fn str_hash(&self, seed: u32) -> Expr {
+        Expr::StrHash(seed)
+    }
+
     pub fn table_get(&self, t: Table, i: Expr) -> Expr {
         Expr::TableGet(t, Box::new(i))
     }
@@ -1,20 +1,152 @@
-use super::{BinOp, Expr};
+use std::collections::HashMap;
+
+use crate::util::to_u32;
+
+use super::{all_bits, BinOp, Expr, ExprBuilder, InputBounds, Instr, Ranges, Reg, Tables, Tac};
 
 pub fn constant_propagation(expr: Expr) -> Expr {
     expr.transform(&|top| match top {
-        Expr::BinOp(BinOp::Add | BinOp::Sub | BinOp::Shll | BinOp::Shrl, a, b)
-            if *b == Expr::Imm(0) =>
-        {
-            *a
-        }
+        Expr::BinOp(op, a, b) => match (*a, *b) {
+            (Expr::Imm(a), Expr::Imm(b)) => Expr::Imm(op.eval(a, b)),
+            (a, Expr::Imm(0)) if op != BinOp::And => a,
+            (Expr::Imm(0), b) if op == BinOp::Add => b,
+            (Expr::Imm(0), _) if op != BinOp::Add && op != BinOp::Sub => Expr::Imm(0),
+            (_, Expr::Imm(0)) => Expr::Imm(0),
+            (a, Expr::Imm(u32::MAX)) if op == BinOp::And => a,
+            (a, b) => ExprBuilder().bin_op(op, a, b),
+        },
         _ => top,
     })
 }
 
+pub fn reassociation(expr: Expr) -> Expr {
+    expr.transform(&|top| {
+        let x = ExprBuilder();
+        match top {
+            Expr::BinOp(outer @ (BinOp::Shll | BinOp::Shrl), a, b) => match (*a, *b) {
+                (Expr::BinOp(inner, e, j), Expr::Imm(k)) if inner == outer => match *j {
+                    Expr::Imm(j) if j + k < 32 => x.bin_op(outer, *e, x.imm(j + k)),
+                    j => x.bin_op(outer, x.bin_op(inner, *e, j), x.imm(k)),
+                },
+                (a, b) => x.bin_op(outer, a, b),
+            },
+            Expr::BinOp(BinOp::Add, _, _) => {
+                let mut terms = Vec::new();
+                top.into_sum_terms(&mut terms);
+
+                // Sum the immediates, then group the remaining terms by how far
+                // they are shifted left, since (a << k) + (b << k) == (a + b) << k
+                // in wrapping arithmetic.
+                let mut imm = 0u32;
+                let mut shifts = Vec::new();
+                let mut groups: HashMap<u32, Vec<Expr>> = HashMap::new();
+                for term in terms {
+                    if let Expr::Imm(n) = term {
+                        imm = imm.wrapping_add(n);
+                        continue;
+                    }
+                    let (base, shift) = term.into_shifted();
+                    if !groups.contains_key(&shift) {
+                        shifts.push(shift);
+                    }
+                    groups.entry(shift).or_default().push(base);
+                }
+
+                let mut sum: Vec<Expr> = shifts
+                    .into_iter()
+                    .map(|shift| x.shll(x.sum(groups.remove(&shift).unwrap()), x.imm(shift)))
+                    .collect();
+                if imm != 0 {
+                    sum.push(x.imm(imm));
+                }
+                constant_propagation(x.sum(sum))
+            }
+            _ => top,
+        }
+    })
+}
+
+pub struct Optimizer<'a> {
+    tables: &'a Tables,
+    hash_table_len: usize,
+    bounds: InputBounds,
+}
+
+impl Optimizer<'_> {
+    pub fn new(tables: &Tables, hash_table_len: usize, bounds: InputBounds) -> Optimizer<'_> {
+        Optimizer {
+            tables,
+            hash_table_len,
+            bounds,
+        }
+    }
+
+    pub fn optimize(&self, tac: &Tac) -> Tac {
+        let mut expr = tac.unflatten_tree(tac.last_reg(), &HashMap::new());
+        expr = self.mask_folding(expr);
+        loop {
+            loop {
+                let next = constant_propagation(reassociation(expr.clone()));
+                if next == expr {
+                    break;
+                }
+                expr = next;
+            }
+
+            let mut tac = Tac::new();
+            tac.push_expr(expr.clone());
+            let (tac, _) = tac.local_value_numbering();
+            let reduced = self.strength_reduction(&tac);
+            if reduced == expr {
+                return tac.dead_instr_elimination();
+            }
+            expr = reduced;
+        }
+    }
+
+    fn mask_folding(&self, expr: Expr) -> Expr {
+        expr.transform(&|top| match top {
+            Expr::TableIndexMask(t) => Expr::Imm(to_u32(self.tables[t].len() - 1)),
+            Expr::HashMask => Expr::Imm(to_u32(self.hash_table_len - 1)),
+            _ => top,
+        })
+    }
+
+    // Remove masks that cannot clear any bits, given the value ranges.
+    fn strength_reduction(&self, tac: &Tac) -> Expr {
+        let ranges = Ranges::new(tac, self.tables, Some(self.hash_table_len), self.bounds);
+
+        let mut reg_to_new_reg = HashMap::new();
+        let mut new_tac = Tac::new();
+        for (i, instr) in tac.instrs().iter().enumerate() {
+            let reg = Reg(i);
+            let new_reg = match *instr {
+                Instr::BinOp(BinOp::And, a, b) if matches!(tac[b], Instr::Imm(mask) if all_bits(ranges[a]) & !mask == 0) => {
+                    reg_to_new_reg[&a]
+                }
+                Instr::Imm(_)
+                | Instr::StrLen
+                | Instr::StrSum(_)
+                | Instr::StrSumBounded { .. }
+                | Instr::StrHash(_)
+                | Instr::TableIndexMask(_)
+                | Instr::HashMask => new_tac.push(*instr),
+                Instr::StrGet(i) => new_tac.push(Instr::StrGet(reg_to_new_reg[&i])),
+                Instr::TableGet(t, i) => new_tac.push(Instr::TableGet(t, reg_to_new_reg[&i])),
+                Instr::BinOp(op, a, b) => {
+                    new_tac.push(Instr::BinOp(op, reg_to_new_reg[&a], reg_to_new_reg[&b]))
+                }
+            };
+            reg_to_new_reg.insert(reg, new_reg);
+        }
+        new_tac.unflatten_tree(reg_to_new_reg[&tac.last_reg()], &HashMap::new())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::ir::ExprBuilder;
+    use crate::ir::{ExprBuilder, Trace};
 
     #[test]
     fn test_remove_zero_shifts() {
@@ -25,7 +157,85 @@ mod test {
                 x.shll(x.shrl(x.hash_mask(), x.imm(0)), x.imm(0)),
                 x.imm(0),
             ])),
-            x.sum(vec![x.shll(x.imm(0), x.imm(2)), x.hash_mask()])
+            x.hash_mask()
+        )
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        let x = ExprBuilder();
+        assert_eq!(
+            constant_propagation(x.add(
+                x.and(x.shrl(x.imm(0xff00), x.imm(8)), x.imm(0x0f)),
+                x.sub(x.imm(1), x.imm(2))
+            )),
+            x.imm(0xe)
+        )
+    }
+
+    #[test]
+    fn test_reassociate_shifted_sum() {
+        let x = ExprBuilder();
+        assert_eq!(
+            reassociation(x.sum(vec![
+                x.shll(x.str_len(), x.imm(2)),
+                x.imm(3),
+                x.shll(x.shll(x.str_get(x.imm(0)), x.imm(1)), x.imm(1)),
+                x.str_get(x.imm(1)),
+                x.imm(4),
+            ])),
+            x.sum(vec![
+                x.shll(x.add(x.str_len(), x.str_get(x.imm(0))), x.imm(2)),
+                x.str_get(x.imm(1)),
+                x.imm(7),
+            ])
+        )
+    }
+
+    #[test]
+    fn test_optimize() {
+        let x = ExprBuilder();
+        let mut tables = Tables::new();
+        let t = tables.push(vec![0, 1, 2, 3]);
+
+        let mut tac = Tac::new();
+        tac.push_expr(x.str_sum(0));
+        let mix = tac.push_expr(x.add(
+            x.shll(x.str_get(x.sub(x.str_len(), x.imm(1))), x.imm(1)),
+            x.shll(x.str_len(), x.imm(1)),
+        ));
+        let hash = tac.push_expr(x.add(
+            x.shrl(x.reg(mix), x.imm(2)),
+            x.table_get(t, x.and(x.reg(mix), x.table_index_mask(t))),
+        ));
+        tac.push_expr(x.and(x.reg(hash), x.hash_mask()));
+
+        let optimized = Optimizer::new(
+            &tables,
+            256,
+            InputBounds {
+                max_key_len: 4,
+                max_symbol: 255,
+            },
         )
+        .optimize(&tac);
+        assert_eq!(
+            optimized.unflatten_tree(optimized.last_reg(), &HashMap::new()),
+            {
+                let mix = x.shll(
+                    x.add(x.str_get(x.sub(x.str_len(), x.imm(1))), x.str_len()),
+                    x.imm(1),
+                );
+                x.add(
+                    x.shrl(mix.clone(), x.imm(2)),
+                    x.table_get(t, x.and(mix, x.imm(3))),
+                )
+            }
+        );
+
+        let keys = vec![vec![1], vec![2, 3], vec![255, 255, 255, 255]];
+        let before = Trace::new(&keys, &tac, &tables, Some(256));
+        let after = Trace::new(&keys, &optimized, &tables, Some(256));
+        assert_eq!(before[tac.last_reg()], after[optimized.last_reg()]);
     }
 }
@@ -0,0 +1,66 @@
+use std::collections::BTreeSet;
+
+use crate::util::{to_u32, to_usize};
+
+use super::{Instr, Tables, Tac};
+
+// The positions of a key of length len that the hash reads, or None if which
+// positions are read depends on the contents of the key.
+pub fn needed_positions(tac: &Tac, tables: &Tables, len: usize) -> Option<BTreeSet<usize>> {
+    let mut positions = BTreeSet::new();
+    // The value of each register, if it only depends on the length.
+    let mut values: Vec<Option<u32>> = Vec::new();
+    for instr in tac.instrs() {
+        let value = match *instr {
+            Instr::Imm(n) => Some(n),
+            Instr::StrGet(r) => {
+                positions.insert(to_usize(values[r.0]?));
+                None
+            }
+            Instr::StrLen => Some(to_u32(len)),
+            Instr::StrSum(_) | Instr::StrHash(_) => {
+                positions.extend(0..len);
+                None
+            }
+            Instr::StrSumBounded { head, tail, .. } => {
+                let tail_start = len.saturating_sub(to_usize(tail));
+                positions.extend((0..len).filter(|&i| i < to_usize(head) || i >= tail_start));
+                None
+            }
+            Instr::TableGet(t, r) => values[r.0].and_then(|i| tables[t].get(to_usize(i)).copied()),
+            Instr::TableIndexMask(t) => Some(to_u32(tables[t].len() - 1)),
+            Instr::HashMask => None,
+            Instr::BinOp(op, a, b) => match (values[a.0], values[b.0]) {
+                (Some(a), Some(b)) => Some(op.eval(a, b)),
+                _ => None,
+            },
+        };
+        values.push(value);
+    }
+    Some(positions)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::ExprBuilder;
+
+    #[test]
+    fn test_needed_positions() {
+        let x = ExprBuilder();
+        let mut tables = Tables::new();
+        let t = tables.push(vec![0, 0, 1, 1, 2]);
+
+        let mut tac = Tac::new();
+        tac.push_expr(x.add(
+            x.str_get(x.sub(x.str_len(), x.imm(1))),
+            x.str_get(x.table_get(t, x.str_len())),
+        ));
+        let positions = |tac: &Tac, len| needed_positions(tac, &tables, len).map(Vec::from_iter);
+        assert_eq!(positions(&tac, 3), Some(vec![1, 2]));
+        assert_eq!(positions(&tac, 4), Some(vec![2, 3]));
+
+        tac.push_expr(x.str_get(x.str_get(x.imm(0))));
+        assert_eq!(positions(&tac, 4), None);
+    }
+}
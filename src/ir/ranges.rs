@@ -0,0 +1,232 @@
+use std::ops::Index;
+
+use crate::util::{to_u32, to_usize};
+
+use super::{BinOp, Instr, Reg, Tables, Tac};
+
+// The smallest value of the form 2^k - 1 that is at least n.
+pub fn all_bits(n: u32) -> u32 {
+    u32::MAX.checked_shr(n.leading_zeros()).unwrap_or(0)
+}
+
+pub fn bit_width(n: u32) -> u32 {
+    32 - n.leading_zeros()
+}
+
+// Bounds on the inputs that the hash function can observe. The hash is only
+// evaluated after the length check, so max_key_len holds for every input, not
+// just the keys.
+#[derive(Clone, Copy)]
+pub struct InputBounds {
+    pub max_key_len: usize,
+    pub max_symbol: u32,
+}
+
+// An upper bound on the value of each register, computed by abstract
+// interpretation over the TAC.
+pub struct Ranges(Vec<u32>);
+
+impl Ranges {
+    pub fn new(
+        tac: &Tac,
+        tables: &Tables,
+        hash_table_len: Option<usize>,
+        bounds: InputBounds,
+    ) -> Ranges {
+        let mut maxes: Vec<u32> = Vec::new();
+        for instr in tac.instrs() {
+            let max = match *instr {
+                Instr::Imm(n) => n,
+                Instr::StrGet(_) => bounds.max_symbol,
+                Instr::StrLen => to_u32(bounds.max_key_len),
+                Instr::StrSum(m) => (0..bounds.max_key_len)
+                    .map(|i| Self::max_sum_term(bounds, i, m))
+                    .fold(0, u32::saturating_add),
+                Instr::StrSumBounded { mask, head, tail } => {
+                    // Any position past the head might be one of the tail.
+                    let head = usize::min(to_usize(head), bounds.max_key_len);
+                    let head_sum = (0..head)
+                        .map(|i| Self::max_sum_term(bounds, i, mask))
+                        .fold(0, u32::saturating_add);
+                    let max_tail_term = (head..bounds.max_key_len)
+                        .map(|i| Self::max_sum_term(bounds, i, mask))
+                        .max()
+                        .unwrap_or(0);
+                    head_sum.saturating_add(max_tail_term.saturating_mul(tail))
+                }
+                Instr::StrHash(_) => u32::MAX,
+                Instr::TableGet(t, i) => {
+                    let reachable = usize::min(to_usize(maxes[i.0]), tables[t].len() - 1);
+                    tables[t][..=reachable].iter().copied().max().unwrap()
+                }
+                Instr::TableIndexMask(t) => to_u32(tables[t].len() - 1),
+                Instr::HashMask => match hash_table_len {
+                    Some(len) => to_u32(len - 1),
+                    None => u32::MAX,
+                },
+                Instr::BinOp(op, a, b) => {
+                    let b_imm = match tac[b] {
+                        Instr::Imm(n) => Some(n),
+                        _ => None,
+                    };
+                    let (a, b) = (maxes[a.0], maxes[b.0]);
+                    match op {
+                        BinOp::Add => a.saturating_add(b),
+                        BinOp::Sub => match b_imm {
+                            Some(0) => a,
+                            _ => u32::MAX,
+                        },
+                        BinOp::And => all_bits(a) & all_bits(b),
+                        BinOp::Shll => match b_imm {
+                            Some(k) if k < 32 && a.leading_zeros() >= k => a << k,
+                            _ => u32::MAX,
+                        },
+                        BinOp::Shrl => match b_imm {
+                            Some(k) if k < 32 => a >> k,
+                            _ => a,
+                        },
+                    }
+                }
+            };
+            maxes.push(max);
+        }
+        Ranges(maxes)
+    }
+
+    fn max_sum_term(bounds: InputBounds, i: usize, mask: u32) -> u32 {
+        let shift = to_u32(i) & mask;
+        if bounds.max_symbol.leading_zeros() >= shift {
+            bounds.max_symbol << shift
+        } else {
+            u32::MAX
+        }
+    }
+
+    pub fn bit_width(&self, reg: Reg) -> u32 {
+        bit_width(self[reg])
+    }
+}
+
+impl Index<Reg> for Ranges {
+    type Output = u32;
+
+    fn index(&self, index: Reg) -> &Self::Output {
+        &self.0[index.0]
+    }
+}
+
+// Clear the bits of each table element that can never affect the hash. For
+// example, only the low bits of a table element matter if it is added to
+// something and then masked.
+pub fn narrow_tables(tac: &Tac, tables: &Tables) -> Tables {
+    let instrs = tac.instrs();
+    let mut demanded = vec![0u32; instrs.len()];
+    demanded[instrs.len() - 1] = u32::MAX;
+    for (i, instr) in instrs.iter().enumerate().rev() {
+        let d = demanded[i];
+        match *instr {
+            Instr::Imm(_)
+            | Instr::StrLen
+            | Instr::StrSum(_)
+            | Instr::StrSumBounded { .. }
+            | Instr::StrHash(_)
+            | Instr::TableIndexMask(_)
+            | Instr::HashMask => (),
+            Instr::StrGet(r) | Instr::TableGet(_, r) => {
+                if d != 0 {
+                    demanded[r.0] = u32::MAX;
+                }
+            }
+            Instr::BinOp(op, a, b) => {
+                let b_imm = match tac[b] {
+                    Instr::Imm(n) => Some(n),
+                    _ => None,
+                };
+                let (da, db) = match (op, b_imm) {
+                    (BinOp::Add | BinOp::Sub, _) => (all_bits(d), all_bits(d)),
+                    (BinOp::And, Some(m)) => (d & m, 0),
+                    (BinOp::And, None) => (d, d),
+                    (BinOp::Shll, Some(k)) if k < 32 => (d >> k, 0),
+                    (BinOp::Shrl, Some(k)) if k < 32 => (d << k, 0),
+                    (BinOp::Shll | BinOp::Shrl, _) => (u32::MAX, u32::MAX),
+                };
+                demanded[a.0] |= da;
+                demanded[b.0] |= db;
+            }
+        }
+    }
+
+    let mut masks = vec![0u32; tables.tables().len()];
+    for (i, instr) in instrs.iter().enumerate() {
+        if let Instr::TableGet(t, _) = *instr {
+            masks[t.0] |= demanded[i];
+        }
+    }
+
+    let narrowed: Vec<Vec<u32>> = tables
+        .tables()
+        .iter()
+        .zip(masks)
+        .map(|(table, mask)| table.iter().map(|&n| n & mask).collect())
+        .collect();
+    narrowed.into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::ExprBuilder;
+
+    const BOUNDS: InputBounds = InputBounds {
+        max_key_len: 10,
+        max_symbol: 255,
+    };
+
+    #[test]
+    fn test_ranges() {
+        let x = ExprBuilder();
+        let mut tables = Tables::new();
+        let t = tables.push(vec![3, 1, 4, 1, 5, 9, 2, 6]);
+
+        let mut tac = Tac::new();
+        let len = tac.push_expr(x.str_len());
+        let sum = tac.push_expr(x.str_sum(1));
+        let bounded_sum = tac.push_expr(x.str_sum_bounded(1, 3, 2));
+        let mix = tac.push_expr(x.add(
+            x.shll(x.str_get(x.sub(x.reg(len), x.imm(1))), x.imm(2)),
+            x.reg(len),
+        ));
+        let get = tac.push_expr(x.table_get(t, x.shrl(x.reg(len), x.imm(1))));
+        let hash = tac.push_expr(x.and(x.reg(mix), x.hash_mask()));
+
+        let ranges = Ranges::new(&tac, &tables, Some(64), BOUNDS);
+        assert_eq!(ranges[len], 10);
+        assert_eq!(ranges[sum], 255 * 5 + 510 * 5);
+        assert_eq!(ranges[bounded_sum], 255 + 510 + 255 + 510 * 2);
+        assert_eq!(ranges[mix], 255 * 4 + 10);
+        assert_eq!(ranges.bit_width(mix), 11);
+        assert_eq!(ranges[get], 9);
+        assert_eq!(ranges[hash], 63);
+    }
+
+    #[test]
+    fn test_narrow_tables() {
+        let x = ExprBuilder();
+        let mut tables = Tables::new();
+        let offsets = tables.push(vec![0x100, 0x1ff, 0x2f0, 0x30f]);
+        let positions = tables.push(vec![0, 1, 2, 0x103]);
+
+        let mut tac = Tac::new();
+        let index = tac.push_expr(x.and(x.str_len(), x.imm(3)));
+        let offset = tac.push_expr(x.table_get(offsets, x.reg(index)));
+        let position = tac.push_expr(x.table_get(positions, x.reg(index)));
+        tac.push_expr(x.and(
+            x.add(x.shll(x.reg(offset), x.imm(2)), x.str_get(x.reg(position))),
+            x.imm(0xff),
+        ));
+
+        let narrowed = narrow_tables(&tac, &tables);
+        assert_eq!(narrowed[offsets], vec![0x00, 0x3f, 0x30, 0x0f]);
+        assert_eq!(narrowed[positions], tables[positions]);
+    }
+}
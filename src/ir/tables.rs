@@ -1,8 +1,13 @@
-use std::ops::Index;
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    ops::Index,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Table(pub usize);
 
+#[derive(Clone)]
 pub struct Tables(Vec<Vec<u32>>);
 
 impl Tables {
@@ -11,6 +16,9 @@ impl Tables {
     }
 
     pub fn push(&mut self, table: Vec<u32>) -> Table {
+        if let Some(i) = self.0.iter().position(|t| *t == table) {
+            return Table(i);
+        }
         self.0.push(table);
         Table(self.0.len() - 1)
     }
@@ -20,6 +28,12 @@ impl Tables {
     }
 }
 
+impl From<Vec<Vec<u32>>> for Tables {
+    fn from(tables: Vec<Vec<u32>>) -> Self {
+        Tables(tables)
+    }
+}
+
 impl Index<Table> for Tables {
     type Output = Vec<u32>;
 
@@ -27,3 +41,139 @@ impl Index<Table> for Tables {
         &self.0[index.0]
     }
 }
+
+// Several tables stored in one backing array, so that tables which are
+// contained in another, or whose suffix is another's prefix, share elements.
+pub struct PackedTables {
+    pub data: Vec<u32>,
+    pub bases: Vec<usize>,
+}
+
+// Finding every table that is contained in another is quadratic in the table
+// lengths, so only do it if there are few enough substrings to index.
+const CONTAINMENT_SUBSTRING_LIMIT: usize = 1 << 20;
+
+impl PackedTables {
+    pub fn new(tables: &[&[u32]]) -> PackedTables {
+        // Longest tables first, so that shorter ones can be found inside them.
+        let mut order: Vec<usize> = (0..tables.len()).collect();
+        order.sort_by_key(|&i| cmp::Reverse(tables[i].len()));
+
+        let substring_count: usize = tables.iter().map(|t| t.len() * (t.len() + 1) / 2).sum();
+        let index_substrings = substring_count <= CONTAINMENT_SUBSTRING_LIMIT;
+
+        // Each table is either a piece of its own, or contained in a piece at
+        // some offset.
+        let mut pieces = Vec::new();
+        let mut containers: Vec<Option<(usize, usize)>> = vec![None; tables.len()];
+        let mut substrings: HashMap<&[u32], (usize, usize)> = HashMap::new();
+        for i in order {
+            let table = tables[i];
+            if let Some(&container) = substrings.get(table) {
+                containers[i] = Some(container);
+                continue;
+            }
+            if index_substrings {
+                for start in 0..table.len() {
+                    for end in start + 1..=table.len() {
+                        substrings.entry(&table[start..end]).or_insert((i, start));
+                    }
+                }
+            } else {
+                substrings.insert(table, (i, 0));
+            }
+            pieces.push(i);
+        }
+
+        // Greedily chain pieces together, longest overlaps first. An overlap
+        // is shorter than both pieces, so the second longest bounds it.
+        let mut lens: Vec<usize> = pieces.iter().map(|&i| tables[i].len()).collect();
+        lens.sort_unstable_by_key(|&len| cmp::Reverse(len));
+        let max_len = lens.get(1).copied().unwrap_or(0);
+        let mut next: HashMap<usize, (usize, usize)> = HashMap::new();
+        let mut has_prev = HashSet::new();
+        let mut chain_tail: HashMap<usize, usize> = pieces.iter().map(|&i| (i, i)).collect();
+        let mut chain_head: HashMap<usize, usize> = pieces.iter().map(|&i| (i, i)).collect();
+        for overlap in (1..max_len).rev() {
+            let mut by_prefix: HashMap<&[u32], Vec<usize>> = HashMap::new();
+            for &i in pieces.iter().rev() {
+                if tables[i].len() > overlap && !has_prev.contains(&i) {
+                    by_prefix.entry(&tables[i][..overlap]).or_default().push(i);
+                }
+            }
+            for &i in &pieces {
+                let table = tables[i];
+                if table.len() <= overlap || next.contains_key(&i) {
+                    continue;
+                }
+                let Some(candidates) = by_prefix.get_mut(&table[table.len() - overlap..]) else {
+                    continue;
+                };
+                // Linking i to the head of its own chain would form a cycle.
+                let head = chain_head[&i];
+                let Some(pos) = candidates.iter().rposition(|&j| j != head) else {
+                    continue;
+                };
+                let j = candidates.remove(pos);
+                next.insert(i, (j, overlap));
+                has_prev.insert(j);
+                let tail = chain_tail[&j];
+                chain_tail.insert(head, tail);
+                chain_head.insert(tail, head);
+            }
+        }
+
+        let mut data = Vec::new();
+        let mut bases = vec![0; tables.len()];
+        for &head in &pieces {
+            if has_prev.contains(&head) {
+                continue;
+            }
+            let mut i = head;
+            bases[i] = data.len();
+            data.extend_from_slice(tables[i]);
+            while let Some(&(j, overlap)) = next.get(&i) {
+                bases[j] = data.len() - overlap;
+                data.extend_from_slice(&tables[j][overlap..]);
+                i = j;
+            }
+        }
+        for (i, container) in containers.into_iter().enumerate() {
+            if let Some((j, offset)) = container {
+                bases[i] = bases[j] + offset;
+            }
+        }
+        PackedTables { data, bases }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_push_deduplicates() {
+        let mut tables = Tables::new();
+        assert_eq!(tables.push(vec![1, 2]), Table(0));
+        assert_eq!(tables.push(vec![3]), Table(1));
+        assert_eq!(tables.push(vec![1, 2]), Table(0));
+        assert_eq!(tables.tables().len(), 2);
+    }
+
+    #[test]
+    fn test_pack() {
+        let tables: [&[u32]; 4] = [&[5, 6, 7], &[1, 2, 3, 4], &[2, 3], &[3, 4, 5, 6]];
+        let packed = PackedTables::new(&tables);
+        assert_eq!(packed.data, vec![1, 2, 3, 4, 5, 6, 7]);
+        for (table, base) in tables.iter().zip(packed.bases) {
+            assert_eq!(&packed.data[base..base + table.len()], *table);
+        }
+    }
+
+    #[test]
+    fn test_pack_disjoint() {
+        let packed = PackedTables::new(&[&[1, 2], &[3, 4]]);
+        assert_eq!(packed.data, vec![1, 2, 3, 4]);
+        assert_eq!(packed.bases, vec![0, 2]);
+    }
+}
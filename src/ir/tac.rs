@@ -11,6 +11,11 @@ pub enum Instr {
     StrGet(Reg),
     StrLen,
     StrSum(u32),
+    // Like StrSum, but only over the first head and last tail symbols.
+    StrSumBounded { mask: u32, head: u32, tail: u32 },
+    // A hash of the whole key with the given seed, for keys that no cheaper
+    // selectors distinguish.
+    StrHash(u32),
     TableGet(Table, Reg),
     TableIndexMask(Table),
     HashMask,
@@ -60,6 +65,8 @@ impl Tac {
                 Instr::Imm(_)
                 | Instr::StrLen
                 | Instr::StrSum(_)
+                | Instr::StrSumBounded { .. }
+                | Instr::StrHash(_)
                 | Instr::TableIndexMask(_)
                 | Instr::HashMask => *instr,
                 Instr::StrGet(i) => Instr::StrGet(reg_to_new_reg[&i]),
@@ -80,6 +87,54 @@ impl Tac {
         (new_tac, reg_to_new_reg)
     }
 
+    pub fn dead_instr_elimination(&self) -> Tac {
+        let mut live = vec![false; self.0.len()];
+        live[self.0.len() - 1] = true;
+        for (i, instr) in self.0.iter().enumerate().rev() {
+            if !live[i] {
+                continue;
+            }
+            match *instr {
+                Instr::Imm(_)
+                | Instr::StrLen
+                | Instr::StrSum(_)
+                | Instr::StrSumBounded { .. }
+                | Instr::StrHash(_)
+                | Instr::TableIndexMask(_)
+                | Instr::HashMask => (),
+                Instr::StrGet(r) | Instr::TableGet(_, r) => {
+                    live[r.0] = true;
+                }
+                Instr::BinOp(_, a, b) => {
+                    live[a.0] = true;
+                    live[b.0] = true;
+                }
+            }
+        }
+
+        let mut reg_to_new_reg = HashMap::new();
+        let mut new_tac = Tac::new();
+        for (i, instr) in self.0.iter().enumerate() {
+            if !live[i] {
+                continue;
+            }
+            let renamed = match *instr {
+                Instr::Imm(_)
+                | Instr::StrLen
+                | Instr::StrSum(_)
+                | Instr::StrSumBounded { .. }
+                | Instr::StrHash(_)
+                | Instr::TableIndexMask(_)
+                | Instr::HashMask => *instr,
+                Instr::StrGet(i) => Instr::StrGet(reg_to_new_reg[&i]),
+                Instr::TableGet(t, i) => Instr::TableGet(t, reg_to_new_reg[&i]),
+                Instr::BinOp(op, a, b) => Instr::BinOp(op, reg_to_new_reg[&a], reg_to_new_reg[&b]),
+            };
+            reg_to_new_reg.insert(Reg(i), new_tac.push(renamed));
+        }
+        new_tac
+    }
+
     pub fn unflatten_dag(&self) -> Exprs {
         let mut refcounts = vec![0usize; self.0.len()];
         for instr in &self.0 {
@@ -87,6 +142,8 @@ impl Tac {
                 Instr::Imm(_)
                 | Instr::StrLen
                 | Instr::StrSum(_)
+                | Instr::StrSumBounded { .. }
+                | Instr::StrHash(_)
                 | Instr::TableIndexMask(_)
                 | Instr::HashMask => (),
                 Instr::StrGet(i) => {
@@ -126,6 +183,8 @@ impl Tac {
             Instr::StrGet(r) => x.str_get(self.unflatten_tree(r, reg_to_var)),
             Instr::StrLen => x.str_len(),
             Instr::StrSum(m) => x.str_sum(m),
+            Instr::StrSumBounded { mask, head, tail } => x.str_sum_bounded(mask, head, tail),
+            Instr::StrHash(seed) => x.str_hash(seed),
             Instr::TableGet(t, r) => x.table_get(t, self.unflatten_tree(r, reg_to_var)),
             Instr::TableIndexMask(t) => x.table_index_mask(t),
             Instr::HashMask => x.hash_mask(),
@@ -169,4 +228,24 @@ mod test {
             )
         )
     }
+
+    #[test]
+    fn test_dead_instr_elimination() {
+        assert_eq!(
+            Tac(vec![
+                Instr::StrLen,
+                Instr::HashMask,
+                Instr::Imm(1),
+                Instr::BinOp(BinOp::Sub, Reg(0), Reg(2)),
+                Instr::StrGet(Reg(3)),
+            ])
+            .dead_instr_elimination(),
+            Tac(vec![
+                Instr::StrLen,
+                Instr::Imm(1),
+                Instr::BinOp(BinOp::Sub, Reg(0), Reg(1)),
+                Instr::StrGet(Reg(2)),
+            ])
+        )
+    }
 }
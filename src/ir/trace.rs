@@ -4,6 +4,33 @@ use crate::util::{to_u32, to_usize};
 
 use super::{Instr, Reg, Tables, Tac};
 
+// The sum of each symbol shifted by its position masked with mask, over the
+// positions among the first head or the last tail. Each position counts once,
+// even if it is in both.
+pub fn str_sum_bounded(key: &[u32], mask: u32, head: u32, tail: u32) -> u32 {
+    let tail_start = key.len().saturating_sub(to_usize(tail));
+    let mut sum = 0u32;
+    for (i, &x) in key.iter().enumerate() {
+        if i < to_usize(head) || i >= tail_start {
+            sum = sum.wrapping_add(x << (to_u32(i) & mask));
+        }
+    }
+    sum
+}
+
+// Jenkins' one-at-a-time hash, starting from seed instead of zero.
+pub fn str_hash(key: &[u32], seed: u32) -> u32 {
+    let mut h = seed;
+    for &x in key {
+        h = h.wrapping_add(x);
+        h = h.wrapping_add(h << 10);
+        h ^= h >> 6;
+    }
+    h = h.wrapping_add(h << 3);
+    h ^= h >> 11;
+    h.wrapping_add(h << 15)
+}
+
 pub struct Trace(Vec<Vec<u32>>);
 
 impl Trace {
@@ -32,6 +59,11 @@ impl Trace {
                         sum
                     })
                     .collect(),
+                Instr::StrSumBounded { mask, head, tail } => keys
+                    .iter()
+                    .map(|key| str_sum_bounded(key, mask, head, tail))
+                    .collect(),
+                Instr::StrHash(seed) => keys.iter().map(|key| str_hash(key, seed)).collect(),
                 Instr::TableGet(t, r) => {
                     regs[r.0].iter().map(|&i| tables[t][to_usize(i)]).collect()
                 }
@@ -59,3 +91,25 @@ impl Index<Reg> for Trace {
         &self.0[index.0]
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_str_sum_bounded() {
+        let key = [1, 2, 3, 4, 5, 6];
+        assert_eq!(str_sum_bounded(&key, 0, 2, 1), 1 + 2 + 6);
+        assert_eq!(str_sum_bounded(&key, 1, 0, 2), 5 + (6 << 1));
+        // Overlapping ends count each symbol once.
+        assert_eq!(str_sum_bounded(&key, 0, 4, 4), 21);
+        assert_eq!(str_sum_bounded(&[], 0, 4, 4), 0);
+    }
+
+    #[test]
+    fn test_str_hash() {
+        // The published value of the unseeded hash.
+        assert_eq!(str_hash(&[u32::from(b'a')], 0), 0xca2e9442);
+        assert_ne!(str_hash(&[u32::from(b'a')], 1), 0xca2e9442);
+    }
+}
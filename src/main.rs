@@ -1,32 +1,132 @@
 mod backend;
 mod combinatorics;
+#[allow(dead_code)]
 mod frontend;
 mod ir;
 mod search;
 mod spec;
 mod util;
 
+use std::env;
+use std::fs;
 use std::io;
 use std::io::BufRead;
+use std::process;
 
-use backend::CBackend;
+use backend::{
+    builtin_template, CBackend, CppBackend, GoBackend, JsBackend, JsBackendConfig, PythonBackend,
+    Template, TemplateBackend,
+};
+use frontend::{args::InputStyle, spec::Target};
 use search::search;
 use spec::Spec;
 
-fn main() {
+fn read_spec(input_style: InputStyle) -> Result<Spec, String> {
     let mut stdin = io::stdin().lock();
     let mut keys: Vec<Vec<u32>> = Vec::new();
+    let mut weights = Vec::new();
     loop {
         let mut line = String::new();
         if stdin.read_line(&mut line).unwrap() == 0 {
             break;
         }
-        keys.push(line.trim().bytes().map(|c| c.into()).collect());
+        let mut key = line.trim();
+        if input_style == InputStyle::Weighted {
+            let Some((weighted_key, weight)) = key.rsplit_once('\t') else {
+                return Err(format!(
+                    "line {}: expected a key and a weight separated by a tab",
+                    keys.len() + 1
+                ));
+            };
+            let Ok(weight) = weight.parse::<u64>() else {
+                return Err(format!(
+                    "line {}: invalid weight {weight:?}",
+                    keys.len() + 1
+                ));
+            };
+            key = weighted_key;
+            weights.push(weight);
+        }
+        keys.push(key.bytes().map(|c| c.into()).collect());
     }
 
-    let spec = Spec::new(keys);
-    let phf = search(&spec).expect("search failed");
+    Ok(match input_style {
+        InputStyle::Keys => Spec::new(keys),
+        InputStyle::Weighted => Spec::with_weights(keys, weights),
+    })
+}
+
+fn main() {
+    let args = match frontend::args::parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {err}");
+            process::exit(2);
+        }
+    };
+
+    // Templates are read before the search, which may take a while.
+    let template = args.template.as_ref().map(|name| {
+        let text = match builtin_template(name) {
+            Some(text) => text.to_string(),
+            None => fs::read_to_string(name).unwrap_or_else(|err| {
+                eprintln!("error: cannot read template {name}: {err}");
+                process::exit(2);
+            }),
+        };
+        Template::parse(&text).unwrap_or_else(|err| {
+            eprintln!("error: template {name}: {err}");
+            process::exit(2);
+        })
+    });
 
-    let c_code = CBackend::new(spec, phf).emit();
-    print!("{}", c_code);
+    let spec = match read_spec(args.input_style) {
+        Ok(spec) => spec,
+        Err(err) => {
+            eprintln!("error: {err}");
+            process::exit(2);
+        }
+    };
+    let phf = match search(&spec, &args.search_options) {
+        Ok(phf) => phf,
+        Err(err) => {
+            eprintln!("error: search failed: {err}");
+            process::exit(1);
+        }
+    };
+
+    let files = match args.target {
+        Target::C => CBackend::new(spec, phf, args.c_backend_config).emit(),
+        Target::Cpp => CppBackend::new(spec, phf, args.cpp_backend_config).emit(),
+        Target::Go => GoBackend::new(spec, phf, args.go_backend_config).emit(),
+        Target::JavaScript | Target::TypeScript => {
+            let config = JsBackendConfig {
+                typescript: args.target == Target::TypeScript,
+            };
+            JsBackend::new(spec, phf, config).emit()
+        }
+        Target::Python => PythonBackend::new(spec, phf).emit(),
+        Target::Template => {
+            let template = template.unwrap();
+            TemplateBackend::new(spec, phf, template)
+                .emit()
+                .unwrap_or_else(|err| {
+                    eprintln!("error: template {}: {err}", args.template.unwrap());
+                    process::exit(2);
+                })
+        }
+        Target::Java | Target::Rust => unreachable!(),
+    };
+    for file in files {
+        match args.output {
+            Some(ref output) => {
+                let path = format!("{output}.{}", file.extension);
+                if let Err(err) = fs::write(&path, file.contents) {
+                    eprintln!("error: cannot write {path}: {err}");
+                    process::exit(2);
+                }
+            }
+            None => print!("{}", file.contents),
+        }
+    }
 }
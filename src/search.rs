@@ -1,11 +1,17 @@
 mod compressor;
 mod compressor_searcher;
+mod cost;
+mod decision_tree;
+mod displacement;
 mod generational_bit_set;
 mod mixer;
 mod phf;
+mod rng;
 mod selector;
 
 use crate::ir::ExprBuilder;
+use crate::ir::Optimizer;
+use crate::ir::Ranges;
 use crate::ir::Tables;
 use crate::ir::Tac;
 use crate::ir::Trace;
@@ -13,17 +19,379 @@ use crate::spec::Spec;
 use crate::util::table_index_mask;
 use crate::util::table_size;
 use crate::util::to_u32;
+use crate::util::to_u64;
 use crate::util::to_usize;
 use compressor::Compressor;
 use compressor_searcher::CompressorSearchSolution;
+pub use cost::CostModel;
+use cost::{sum_bound_within, worst_case_instrs, Cost};
+pub use decision_tree::DecisionTree;
+use displacement::Displacement;
 use mixer::Mixer;
 pub use phf::Phf;
+use rng::Rng;
 use selector::Selector;
+pub use selector::{SelectorFamilies, SelectorLimits, MAX_SUM_BOUND, MAX_SUM_MASKS};
 use std::time::Instant;
 
-pub fn search(spec: &Spec) -> Option<Phf> {
+#[derive(Clone, Copy, Default)]
+pub struct SearchOptions {
+    // If set, explore several candidates and keep the cheapest. Otherwise,
+    // keep the first one found.
+    pub cost_model: Option<CostModel>,
+    pub selector_limits: SelectorLimits,
+    pub strategy: Strategy,
+    // If set, randomize the search starting from this seed, and retry with
+    // the following seeds if it fails. Otherwise, search deterministically.
+    pub seed: Option<u64>,
+    // If set, the most instructions that hashing any input may take, for
+    // lookups on untrusted input. Only hashes whose loops over the key stay
+    // within it are accepted, and sums over the key are bounded to fit it.
+    pub max_instrs: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    // Displacement for sets of at least DISPLACEMENT_MIN_KEYS keys, selectors
+    // otherwise.
+    #[default]
+    Auto,
+    // Hash the fewest symbols that tell the keys apart. Makes the fastest hash
+    // functions, but the search slows down as the set grows.
+    Selectors,
+    // Hash every key in full and place them with a table of displacements.
+    // Scales to millions of keys.
+    Displacement,
+}
+
+// The smallest set that the automatic strategy searches by displacement.
+pub const DISPLACEMENT_MIN_KEYS: usize = 1 << 16;
+
+// How many selector sets to try when exploring.
+const EXPLORE_SELECTOR_CANDIDATES: usize = 3;
+
+// How many hash table sizes past the smallest to try when exploring. A larger
+// hash table can make a compressor unnecessary.
+const EXPLORE_EXTRA_HASH_BITS: u32 = 1;
+
+// How many ways to compress the mix of each selector set to try when
+// exploring. Later ones have larger offset tables or shift the mix further,
+// which the cost model weighs.
+const EXPLORE_COMPRESSOR_CANDIDATES: usize = 3;
+
+// Failing to mix or compress the selected symbols is rare, and not caused by
+// any limit that can be configured, so the search falls back to displacement,
+// which places any set of unique keys.
+const BUILD_FAILED: &str = "could not combine the selected symbols into a hash";
+
+// How many seeds to try, starting from the one given, before giving up.
+const MAX_SEED_ATTEMPTS: u64 = 8;
+
+pub fn search(spec: &Spec, options: &SearchOptions) -> Result<Phf, String> {
+    let phf = match options.seed {
+        Some(first_seed) => search_seeded(spec, options, first_seed)?,
+        None => search_with_rng(spec, options, None)?,
+    };
+    report_worst_case(spec, &phf.tac);
+    Ok(phf)
+}
+
+fn search_seeded(spec: &Spec, options: &SearchOptions, first_seed: u64) -> Result<Phf, String> {
+    let mut last_err = String::new();
+    for attempt in 0..MAX_SEED_ATTEMPTS {
+        let seed = first_seed.wrapping_add(attempt);
+        match search_with_rng(spec, options, Some(&mut Rng::new(seed))) {
+            Ok(phf) => {
+                eprintln!("search succeeded with seed {seed}");
+                return Ok(phf);
+            }
+            Err(err) => {
+                eprintln!("search with seed {seed} failed: {err}");
+                last_err = err;
+            }
+        }
+    }
+    Err(format!(
+        "{last_err} (tried {MAX_SEED_ATTEMPTS} seeds starting from {first_seed})"
+    ))
+}
+
+fn search_with_rng(
+    spec: &Spec,
+    options: &SearchOptions,
+    mut rng: Option<&mut Rng>,
+) -> Result<Phf, String> {
+    let use_displacement = match options.strategy {
+        Strategy::Auto if spec.interpreted_keys.len() >= DISPLACEMENT_MIN_KEYS => {
+            eprintln!(
+                "searching by displacement, since there are at least {DISPLACEMENT_MIN_KEYS} \
+                 keys (--strategy=selectors overrides this)"
+            );
+            true
+        }
+        Strategy::Auto | Strategy::Selectors => false,
+        Strategy::Displacement => true,
+    };
+    if use_displacement {
+        return search_displacement(spec, options.max_instrs, rng);
+    }
+    match search_selectors(spec, options, rng.as_deref_mut()) {
+        Err(err) if err == BUILD_FAILED => {
+            eprintln!("warning: {err}, falling back to searching by displacement");
+            search_displacement(spec, options.max_instrs, rng)
+        }
+        result => result,
+    }
+}
+
+fn search_selectors(
+    spec: &Spec,
+    options: &SearchOptions,
+    mut rng: Option<&mut Rng>,
+) -> Result<Phf, String> {
+    let max_instrs = options.max_instrs;
+    let mut limits = options.selector_limits;
+    let Some(cost_model) = options.cost_model else {
+        loop {
+            let start = Instant::now();
+            let sels = Selector::search(spec, &limits, 1, false, rng.as_deref_mut())?.remove(0);
+            eprintln!("selector search took {} us", start.elapsed().as_micros());
+            let solution = build(spec, sels, spec.min_hash_bits, 1, rng.as_deref_mut())
+                .pop()
+                .ok_or(BUILD_FAILED)?;
+            match check_max_instrs(spec, &solution, max_instrs) {
+                Ok(()) => return Ok(Phf::new(spec, solution, max_instrs)),
+                Err(err) => bound_sums(spec, &mut limits, &solution, max_instrs, err)?,
+            }
+        }
+    };
+
+    loop {
+        match explore(spec, &limits, cost_model, max_instrs, rng.as_deref_mut())? {
+            Ok(phf) => return Ok(phf),
+            Err((err, solution)) => bound_sums(spec, &mut limits, &solution, max_instrs, err)?,
+        }
+    }
+}
+
+// Keeps the cheapest of several candidates. Fails with the last candidate
+// that went over max_instrs, if every candidate that was built did.
+fn explore(
+    spec: &Spec,
+    limits: &SelectorLimits,
+    cost_model: CostModel,
+    max_instrs: Option<u64>,
+    mut rng: Option<&mut Rng>,
+) -> Result<Result<Phf, (String, CompressorSearchSolution)>, String> {
     let start = Instant::now();
-    let sels = Selector::search(spec)?;
+    let candidates = Selector::search(
+        spec,
+        limits,
+        EXPLORE_SELECTOR_CANDIDATES,
+        true,
+        rng.as_deref_mut(),
+    )?;
+    eprintln!("selector search took {} us", start.elapsed().as_micros());
+
+    // Trees are weighed against the hash functions below, and kept if they
+    // score better than all of them.
+    let mut best_tree: Option<(u64, DecisionTree)> = None;
+    for sels in &candidates {
+        let Some(tree) = DecisionTree::new(spec, sels) else {
+            continue;
+        };
+        let cost = Cost::of_tree(&tree);
+        let score = cost.score(cost_model);
+        eprintln!("decision tree scores {score}: {cost:?}");
+        if best_tree
+            .as_ref()
+            .is_none_or(|&(best_score, _)| score < best_score)
+        {
+            best_tree = Some((score, tree));
+        }
+    }
+
+    let mut best: Option<(u64, CompressorSearchSolution)> = None;
+    let mut over_limit = None;
+    for sels in candidates {
+        for hash_bits in spec.min_hash_bits..=spec.min_hash_bits + EXPLORE_EXTRA_HASH_BITS {
+            let solutions = build(
+                spec,
+                sels.clone(),
+                hash_bits,
+                EXPLORE_COMPRESSOR_CANDIDATES,
+                rng.as_deref_mut(),
+            );
+            for solution in solutions {
+                if let Err(err) = check_max_instrs(spec, &solution, max_instrs) {
+                    eprintln!("candidate with {hash_bits} hash bits rejected: {err}");
+                    over_limit = Some((err, solution));
+                    continue;
+                }
+                let cost = Cost::new(
+                    &solution.tac,
+                    &solution.tables,
+                    table_size(hash_bits),
+                    spec.input_bounds(),
+                );
+                let score = cost.score(cost_model);
+                eprintln!("candidate with {hash_bits} hash bits scores {score}: {cost:?}");
+                if best
+                    .as_ref()
+                    .is_none_or(|&(best_score, _)| score < best_score)
+                {
+                    best = Some((score, solution));
+                }
+            }
+        }
+    }
+    let Some((score, solution)) = best else {
+        return over_limit.map(Err).ok_or(BUILD_FAILED.into());
+    };
+    let mut phf = Phf::new(spec, solution, max_instrs);
+    phf.tree = best_tree
+        .filter(|&(tree_score, _)| tree_score < score)
+        .map(|(_, tree)| tree);
+    if phf.tree.is_some() {
+        eprintln!("using a decision tree for lookups");
+    }
+    Ok(Ok(phf))
+}
+
+// Cost models are not consulted: there is only one kind of displacement hash,
+// and the smallest table that fits it is also the cheapest.
+fn search_displacement(
+    spec: &Spec,
+    max_instrs: Option<u64>,
+    mut rng: Option<&mut Rng>,
+) -> Result<Phf, String> {
+    spec.check_unique()?;
+    for hash_bits in spec.min_hash_bits..=spec.min_hash_bits + EXPLORE_EXTRA_HASH_BITS {
+        let start = Instant::now();
+        let displacement =
+            Displacement::search(&spec.interpreted_keys, hash_bits, rng.as_deref_mut());
+        eprintln!(
+            "displacement search with {hash_bits} hash bits took {} ms",
+            start.elapsed().as_millis()
+        );
+        let Some(displacement) = displacement else {
+            continue;
+        };
+
+        let mut tac = Tac::new();
+        let mut tables = Tables::new();
+        let unmasked_hash_reg = displacement.compile(&mut tac, &mut tables);
+        let x = ExprBuilder();
+        tac.push_expr(x.and(x.reg(unmasked_hash_reg), x.hash_mask()));
+        let solution = CompressorSearchSolution {
+            tac,
+            tables,
+            hash_bits,
+        };
+        check_max_instrs(spec, &solution, max_instrs)?;
+        let start = Instant::now();
+        let phf = Phf::new(spec, solution, max_instrs);
+        eprintln!("validation took {} ms", start.elapsed().as_millis());
+        return Ok(phf);
+    }
+    Err("could not place the keys with displacements".to_string())
+}
+
+// The TAC as Phf optimizes it, which is what the backends emit.
+fn optimized_tac(spec: &Spec, solution: &CompressorSearchSolution) -> Tac {
+    Optimizer::new(
+        &solution.tables,
+        table_size(solution.hash_bits),
+        spec.input_bounds(),
+    )
+    .optimize(&solution.tac)
+}
+
+fn check_max_instrs(
+    spec: &Spec,
+    solution: &CompressorSearchSolution,
+    max_instrs: Option<u64>,
+) -> Result<(), String> {
+    let Some(max_instrs) = max_instrs else {
+        return Ok(());
+    };
+    let tac = optimized_tac(spec, solution);
+    let (len, instrs) = (spec.min_interpreted_key_len..=spec.max_interpreted_key_len)
+        .map(|len| (len, worst_case_instrs(&tac, len)))
+        .max_by_key(|&(_, instrs)| instrs)
+        .unwrap();
+    if instrs > max_instrs {
+        return Err(format!(
+            "hashing a key of length {len} may take {instrs} instructions, over the limit of {max_instrs}"
+        ));
+    }
+    Ok(())
+}
+
+// After a hash went over max_instrs with err, bounds its sum to as many
+// symbols as the limit leaves room for, so that the next search finds a
+// cheaper one. Fails with err if the hash has no sum to bound further.
+fn bound_sums(
+    spec: &Spec,
+    limits: &mut SelectorLimits,
+    solution: &CompressorSearchSolution,
+    max_instrs: Option<u64>,
+    err: String,
+) -> Result<(), String> {
+    let tac = optimized_tac(spec, solution);
+    // The instruction count only grows with the length of the key.
+    let bound = max_instrs
+        .and_then(|max| sum_bound_within(&tac, spec.max_interpreted_key_len, max))
+        .ok_or_else(|| err.clone())?;
+    eprintln!("{err}, retrying with --sum-bound={bound}");
+    limits.sum_bound = Some(bound);
+    Ok(())
+}
+
+// Lists the most instructions that hashing a key of each length may take.
+// Lengths are grouped into runs where the count grows by the same step, as it
+// does while a loop reads every symbol, so that long keys take a few lines.
+fn report_worst_case(spec: &Spec, tac: &Tac) {
+    // The first and last length of each run, the count at its first length,
+    // and the step between lengths.
+    let mut runs: Vec<(usize, usize, u64, u64)> = Vec::new();
+    for len in spec.min_interpreted_key_len..=spec.max_interpreted_key_len {
+        let instrs = worst_case_instrs(tac, len);
+        match runs.last_mut() {
+            Some((start, end, first, step)) if instrs == *first + *step * to_u64(len - *start) => {
+                *end = len;
+            }
+            Some((start, end, first, step)) if start == end && instrs >= *first => {
+                *end = len;
+                *step = instrs - *first;
+            }
+            _ => runs.push((len, len, instrs, 0)),
+        }
+    }
+
+    eprintln!("worst-case instructions by key length:");
+    for (start, end, first, step) in runs {
+        if start == end {
+            eprintln!("  {start}: {first}");
+        } else if step == 0 {
+            eprintln!("  {start}-{end}: {first}");
+        } else {
+            let last = first + step * to_u64(end - start);
+            eprintln!("  {start}-{end}: {first} to {last}, {step} more per symbol");
+        }
+    }
+    eprintln!("  other lengths: rejected before reading the key");
+}
+
+// Builds hashes of the selected symbols, one for each of up to
+// max_compressors ways to compress them, or one if they need no compressing.
+fn build(
+    spec: &Spec,
+    sels: Vec<Selector>,
+    hash_bits: u32,
+    max_compressors: usize,
+    mut rng: Option<&mut Rng>,
+) -> Vec<CompressorSearchSolution> {
     eprintln!("found selectors: {sels:?}");
     let mut tac = Tac::new();
     let mut tables = Tables::new();
@@ -31,7 +399,6 @@ pub fn search(spec: &Spec) -> Option<Phf> {
         .into_iter()
         .map(|sel| sel.compile(&mut tac, &mut tables))
         .collect();
-    eprintln!("selector search took {} us", start.elapsed().as_micros());
 
     let start = Instant::now();
     let trace = Trace::new(&spec.interpreted_keys, &tac, &tables, None);
@@ -39,14 +406,22 @@ pub fn search(spec: &Spec) -> Option<Phf> {
 
     let sel_cols: Vec<&[u32]> = sel_regs.iter().map(|&reg| &trace[reg]).collect();
     let start = Instant::now();
-    let mixer = Mixer::search(&sel_cols)?;
+    let Some(mixer) = Mixer::search(&sel_cols, rng.as_deref_mut()) else {
+        return Vec::new();
+    };
     eprintln!("mixer search took {} us", start.elapsed().as_micros());
     eprintln!("mixer has {} bits", mixer.mix_bits);
 
     let mix_reg = mixer.compile(&mut tac, &sel_regs);
-    let unmasked_hash_reg = if mixer.mix_bits <= spec.min_hash_bits {
-        let size = table_size(spec.min_hash_bits);
-        let mask = table_index_mask(spec.min_hash_bits);
+    let ranges = Ranges::new(&tac, &tables, None, spec.input_bounds());
+    eprintln!(
+        "mix is statically bounded to {} bits",
+        ranges.bit_width(mix_reg)
+    );
+    let x = ExprBuilder();
+    if mixer.mix_bits <= hash_bits {
+        let size = table_size(hash_bits);
+        let mask = table_index_mask(hash_bits);
 
         let mut seen = vec![false; size];
         for &mix in &mixer.mixes {
@@ -60,33 +435,147 @@ pub fn search(spec: &Spec) -> Option<Phf> {
             }
         }
         let rotation = rotation.unwrap();
-        let x = ExprBuilder();
-        tac.push_expr(x.add(x.reg(mix_reg), x.imm(rotation)))
-    } else {
-        let mut bitwidth = mixer.mix_bits;
-        let mut values = mixer.mixes;
-        let mut reg = mix_reg;
-        while bitwidth > spec.min_hash_bits {
+        let unmasked_hash_reg = tac.push_expr(x.add(x.reg(mix_reg), x.imm(rotation)));
+        tac.push_expr(x.and(x.reg(unmasked_hash_reg), x.hash_mask()));
+        return vec![CompressorSearchSolution {
+            tac,
+            tables,
+            hash_bits,
+        }];
+    }
+
+    let weights = spec.interpreted_weights.as_deref();
+    let start = Instant::now();
+    let candidates = Compressor::search(
+        &mixer.mixes,
+        mixer.mix_bits,
+        hash_bits,
+        hash_bits,
+        weights,
+        max_compressors,
+        rng.as_deref_mut(),
+    );
+    eprintln!("compressor search took {} ms", start.elapsed().as_millis());
+    let mut solutions = Vec::new();
+    'candidates: for (compressor, mut values) in candidates {
+        let mut tac = tac.clone();
+        let mut tables = tables.clone();
+        let mut bitwidth = compressor.bitwidth;
+        let mut reg = compressor.compile(&mut tac, &mut tables, mix_reg);
+        // Only the first compressor has alternatives.
+        while bitwidth > hash_bits {
             let start = Instant::now();
-            let (compressor, new_values) =
-                Compressor::search(&values, bitwidth, spec.min_hash_bits, spec.min_hash_bits)?;
+            let Some((compressor, new_values)) = Compressor::search(
+                &values,
+                bitwidth,
+                hash_bits,
+                hash_bits,
+                weights,
+                1,
+                rng.as_deref_mut(),
+            )
+            .pop() else {
+                continue 'candidates;
+            };
             eprintln!("compressor search took {} ms", start.elapsed().as_millis());
             bitwidth = compressor.bitwidth;
             values = new_values;
             reg = compressor.compile(&mut tac, &mut tables, reg);
         }
-        reg
-    };
-
-    let x = ExprBuilder();
-    tac.push_expr(x.and(x.reg(unmasked_hash_reg), x.hash_mask()));
-
-    Some(Phf::new(
-        spec,
-        CompressorSearchSolution {
+        tac.push_expr(x.and(x.reg(reg), x.hash_mask()));
+        solutions.push(CompressorSearchSolution {
             tac,
             tables,
-            hash_bits: spec.min_hash_bits,
-        },
-    ))
+            hash_bits,
+        });
+    }
+    solutions
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_seed_is_reproducible() {
+        let keys: Vec<Vec<u32>> = (0..300)
+            .map(|i: u32| format!("key{}", i * 7919).bytes().map(u32::from).collect())
+            .collect();
+        let spec = Spec::new(keys);
+        for strategy in [Strategy::Selectors, Strategy::Displacement] {
+            let options = SearchOptions {
+                strategy,
+                seed: Some(12),
+                ..SearchOptions::default()
+            };
+            let a = search(&spec, &options).unwrap();
+            let b = search(&spec, &options).unwrap();
+            assert_eq!(a.tac, b.tac);
+            assert_eq!(a.key_table, b.key_table);
+        }
+    }
+
+    #[test]
+    fn test_max_instrs() {
+        let spec = Spec::new(
+            ["if", "else", "while"]
+                .iter()
+                .map(|key| key.bytes().map(u32::from).collect())
+                .collect(),
+        );
+        // The table of pilots has one entry, so the hash that picks it is
+        // optimized out, and the other loops over the whole key, which has up
+        // to five symbols.
+        let options = SearchOptions {
+            strategy: Strategy::Displacement,
+            max_instrs: Some(41),
+            ..SearchOptions::default()
+        };
+        assert_eq!(
+            search(&spec, &options).err(),
+            Some("hashing a key of length 5 may take 42 instructions, over the limit of 41".into())
+        );
+        let options = SearchOptions {
+            max_instrs: Some(42),
+            ..options
+        };
+        assert!(search(&spec, &options).is_ok());
+    }
+
+    #[test]
+    fn test_max_instrs_bounds_sums() {
+        // Keys that differ only in their first and last symbols, distinguished
+        // by sums alone.
+        let spec = Spec::new(
+            ["a", "b", "c"]
+                .iter()
+                .flat_map(|head| {
+                    ["x", "y", "z"].map(|tail| format!("{head}{}{tail}", "m".repeat(30)))
+                })
+                .map(|key| key.bytes().map(u32::from).collect())
+                .collect(),
+        );
+        let families = SelectorFamilies {
+            len: false,
+            index: false,
+            arith: false,
+            table: false,
+            sum: true,
+            hash: false,
+        };
+        for cost_model in [None, Some(CostModel::Smallest)] {
+            let options = SearchOptions {
+                cost_model,
+                selector_limits: SelectorLimits {
+                    families,
+                    ..SelectorLimits::default()
+                },
+                strategy: Strategy::Selectors,
+                max_instrs: Some(40),
+                ..SearchOptions::default()
+            };
+            let phf = search(&spec, &options).unwrap();
+            assert!(worst_case_instrs(&phf.tac, 32) <= 40);
+        }
+    }
 }
@@ -5,7 +5,10 @@ use crate::{
     util::{table_index_mask, table_size, to_u32, to_usize},
 };
 
-use super::generational_bit_set::{BitSet, GenerationalBitSet};
+use super::{
+    generational_bit_set::{BitSet, GenerationalBitSet},
+    rng::Rng,
+};
 
 pub struct Compressor {
     pub bitwidth: u32,
@@ -14,21 +17,30 @@ pub struct Compressor {
 }
 
 impl Compressor {
+    // Finds up to max_candidates compressors, each with the hashes of the
+    // values. Candidates that reach out_bitwidth come first, smallest offset
+    // table first; one that only gets closer to it is returned if there are
+    // none.
     pub fn search(
         values: &[u32],
         in_bitwidth: u32,
         out_bitwidth: u32,
         max_table_bits: u32,
-    ) -> Option<(Compressor, Vec<u32>)> {
+        weights: Option<&[u64]>,
+        max_candidates: usize,
+        mut rng: Option<&mut Rng>,
+    ) -> Vec<(Compressor, Vec<u32>)> {
         let mut seen = GenerationalBitSet::new(table_size(max_table_bits));
 
+        let mut candidates = Vec::new();
         let mut last_groups = None;
         for offset_index_bits in 1..=max_table_bits {
-            let groups = Self::group_values(values, offset_index_bits);
+            let groups = Self::group_values(values, weights, offset_index_bits, rng.as_deref_mut());
 
             for base_shift in (in_bitwidth - out_bitwidth)..=offset_index_bits {
                 let start = Instant::now();
                 let opt = Self::find_offset_table(
+                    values,
                     &groups,
                     out_bitwidth,
                     offset_index_bits,
@@ -37,7 +49,7 @@ impl Compressor {
                 );
                 eprintln!("offset table search for offset_index_bits={offset_index_bits} base_shift={base_shift} took {} us", start.elapsed().as_micros());
                 if let Some((offset_table, values)) = opt {
-                    return Some((
+                    candidates.push((
                         Compressor {
                             bitwidth: out_bitwidth,
                             base_shift,
@@ -45,11 +57,17 @@ impl Compressor {
                         },
                         values,
                     ));
+                    if candidates.len() == max_candidates {
+                        return candidates;
+                    }
                 }
             }
 
             last_groups = Some(groups);
         }
+        if !candidates.is_empty() {
+            return candidates;
+        }
 
         let mut seen = HashSet::with_capacity(values.len());
         for target_bitwidth in out_bitwidth + 1..in_bitwidth {
@@ -58,6 +76,7 @@ impl Compressor {
             let base_shift = in_bitwidth - target_bitwidth;
             let start = Instant::now();
             let opt = Self::find_offset_table(
+                values,
                 groups,
                 target_bitwidth,
                 max_table_bits,
@@ -66,35 +85,50 @@ impl Compressor {
             );
             eprintln!("offset table search for offset_index_bits={max_table_bits} base_shift={base_shift} took {} us", start.elapsed().as_micros());
             if let Some((offset_table, values)) = opt {
-                return Some((
+                return vec![(
                     Compressor {
                         bitwidth: target_bitwidth,
                         base_shift,
                         offset_table,
                     },
                     values,
-                ));
+                )];
             }
         }
-        None
+        Vec::new()
     }
 
-    fn group_values(values: &[u32], offset_index_bits: u32) -> Vec<Vec<u32>> {
+    // Groups are placed largest first. Among groups of the same size, the ones
+    // holding the most frequently looked up keys go first, so that they get
+    // the lowest free slots and end up close together in the hash table. With
+    // an rng, any remaining ties are broken randomly.
+    fn group_values(
+        values: &[u32],
+        weights: Option<&[u64]>,
+        offset_index_bits: u32,
+        rng: Option<&mut Rng>,
+    ) -> Vec<Vec<u32>> {
         let offset_table_size = table_size(offset_index_bits);
         let offset_table_index_mask = table_index_mask(offset_index_bits);
 
-        let mut groups = vec![Vec::new(); offset_table_size];
-        for &value in values {
+        let mut groups = vec![(0u64, Vec::new()); offset_table_size];
+        for (i, &value) in values.iter().enumerate() {
             let offset_index = value & offset_table_index_mask;
-            groups[to_usize(offset_index)].push(value);
+            let (weight, group) = &mut groups[to_usize(offset_index)];
+            *weight += weights.map_or(0, |weights| weights[i]);
+            group.push(value);
         }
 
-        groups.retain(|group| !group.is_empty());
-        groups.sort_by_key(|group| cmp::Reverse(group.len()));
-        groups
+        groups.retain(|(_, group)| !group.is_empty());
+        if let Some(rng) = rng {
+            rng.shuffle(&mut groups);
+        }
+        groups.sort_by_key(|(weight, group)| cmp::Reverse((group.len(), *weight)));
+        groups.into_iter().map(|(_, group)| group).collect()
     }
 
     fn find_offset_table<B>(
+        values: &[u32],
         groups: &[Vec<u32>],
         out_bitwidth: u32,
         offset_index_bits: u32,
@@ -115,8 +149,7 @@ impl Compressor {
 
         let mut offset_table = vec![0; offset_table_size];
         let offset_size = to_u32(hash_table_size);
-
-        let mut unmasked_hashes = Vec::new();
+        let mut group_hashes = Vec::new();
 
         for group in groups {
             let mut good_offset = None;
@@ -128,11 +161,15 @@ impl Compressor {
                     Some(to_u32(full_before).wrapping_sub(group[0] >> base_shift) & hash_mask);
             } else {
                 'offset: for offset in 0..offset_size {
+                    // When base_shift is past the offset index bits, values in
+                    // the same group can also collide with each other.
+                    group_hashes.clear();
                     for &value in group {
                         let hash = (value >> base_shift).wrapping_add(offset) & hash_mask;
-                        if seen.test(hash) {
+                        if seen.test(hash) || group_hashes.contains(&hash) {
                             continue 'offset;
                         }
+                        group_hashes.push(hash);
                     }
 
                     good_offset = Some(offset);
@@ -142,9 +179,7 @@ impl Compressor {
 
             if let Some(offset) = good_offset {
                 for &mix in group {
-                    let unmasked_hash = (mix >> base_shift).wrapping_add(offset);
-                    seen.set(unmasked_hash & hash_mask);
-                    unmasked_hashes.push(unmasked_hash);
+                    seen.set((mix >> base_shift).wrapping_add(offset) & hash_mask);
                 }
                 let offset_table_index = group[0] & offset_table_index_mask;
                 offset_table[to_usize(offset_table_index)] = offset;
@@ -153,6 +188,15 @@ impl Compressor {
             }
         }
 
+        // Keep the hashes in the same order as the values, so that they still
+        // line up with the weights.
+        let unmasked_hashes = values
+            .iter()
+            .map(|&mix| {
+                let offset_table_index = mix & offset_table_index_mask;
+                (mix >> base_shift).wrapping_add(offset_table[to_usize(offset_table_index)])
+            })
+            .collect();
         Some((offset_table, unmasked_hashes))
     }
 
@@ -168,3 +212,55 @@ impl Compressor {
         ))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_group_values_by_weight() {
+        let values = [0b00, 0b01, 0b10, 0b11, 0b100];
+        let groups = Compressor::group_values(&values, Some(&[1, 1, 1, 9, 1]), 2, None);
+        assert_eq!(
+            groups,
+            vec![vec![0b00, 0b100], vec![0b11], vec![0b01], vec![0b10]]
+        );
+    }
+
+    #[test]
+    fn test_no_collisions_within_group() {
+        // Every value falls in the same group, and differs only in bits that a
+        // wide base_shift would drop.
+        let values: Vec<u32> = (1..40).map(|i| i << 9).collect();
+        let (compressor, hashes) = Compressor::search(&values, 26, 7, 7, None, 1, None).remove(0);
+        let mask = table_index_mask(compressor.bitwidth);
+        let distinct: HashSet<u32> = hashes.iter().map(|&hash| hash & mask).collect();
+        assert_eq!(distinct.len(), values.len());
+        assert!(!distinct.contains(&0));
+    }
+
+    #[test]
+    fn test_hashes_follow_value_order() {
+        let values: Vec<u32> = (0..32).map(|i| i * 37 % 256).collect();
+        let (compressor, hashes) = Compressor::search(&values, 8, 6, 6, None, 1, None).remove(0);
+        let index_mask = to_u32(compressor.offset_table.len() - 1);
+        for (&value, &hash) in values.iter().zip(&hashes) {
+            let offset = compressor.offset_table[to_usize(value & index_mask)];
+            assert_eq!(hash, (value >> compressor.base_shift).wrapping_add(offset));
+        }
+    }
+
+    #[test]
+    fn test_several_candidates() {
+        let values: Vec<u32> = (0..32).map(|i| i * 37 % 256).collect();
+        let candidates = Compressor::search(&values, 8, 6, 6, None, 3, None);
+        assert_eq!(candidates.len(), 3);
+        let mut tables = HashSet::new();
+        for (compressor, hashes) in candidates {
+            assert_eq!(compressor.bitwidth, 6);
+            let distinct: HashSet<u32> = hashes.iter().map(|&hash| hash & 63).collect();
+            assert_eq!(distinct.len(), values.len());
+            assert!(tables.insert((compressor.base_shift, compressor.offset_table)));
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::HashSet;
+
+use crate::{
+    ir::{bit_width, narrow_tables, Expr, InputBounds, Instr, Optimizer, Tables, Tac},
+    util::{to_u32, to_u64, to_usize},
+};
+
+use super::decision_tree::DecisionTree;
+
+// How to weigh the parts of a cost against each other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CostModel {
+    // Minimize the size of the tables and code.
+    Smallest,
+    // Minimize the time taken to hash a key.
+    Fastest,
+}
+
+// A static estimate of what a hash function costs, measured on the TAC that
+// the backends will emit.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Cost {
+    // Instructions other than immediates, which get folded into operands.
+    pub instrs: usize,
+    // Loads from the key or from tables.
+    pub loads: usize,
+    // Loops over the whole key.
+    pub loops: usize,
+    // Bytes of tables used by the hash function.
+    pub table_bytes: usize,
+    // Slots in the hash table.
+    pub slots: usize,
+    // Case labels in a decision tree, which take code space but are mostly
+    // not executed.
+    pub cases: usize,
+    // Branches on the way to the deepest key of a decision tree.
+    pub branches: usize,
+}
+
+// A rough size of a hash table slot holding a pointer, a length and a value.
+const SLOT_BYTES: u64 = 16;
+
+// A rough number of instructions that a loop over a typical key takes.
+const LOOP_INSTRS: u64 = 32;
+
+// A rough number of instructions that a branch costs, as branching on the
+// symbols of the key is hard to predict.
+const BRANCH_INSTRS: u64 = 8;
+
+// What a loop spends on each symbol of the key that it reads: a load, a shift
+// and an add for sums, and a load and five steps for Jenkins' hash.
+const SUM_INSTRS_PER_SYMBOL: u64 = 3;
+const HASH_INSTRS_PER_SYMBOL: u64 = 6;
+const HASH_FINISH_INSTRS: u64 = 6;
+
+// The most instructions that the TAC executes for a key of the given length,
+// counting each symbol that a loop reads. The hash functions reject keys of
+// any other length before running the TAC, so the most this returns for an
+// interpreted key length bounds the work that any input can cause.
+pub fn worst_case_instrs(tac: &Tac, key_len: usize) -> u64 {
+    let len = to_u64(key_len);
+    tac.instrs()
+        .iter()
+        .map(|instr| match *instr {
+            Instr::Imm(_) => 0,
+            Instr::StrSum(_) => len * SUM_INSTRS_PER_SYMBOL,
+            Instr::StrSumBounded { head, tail, .. } => {
+                len.min(u64::from(head) + u64::from(tail)) * SUM_INSTRS_PER_SYMBOL
+            }
+            Instr::StrHash(_) => len * HASH_INSTRS_PER_SYMBOL + HASH_FINISH_INSTRS,
+            Instr::StrGet(_)
+            | Instr::StrLen
+            | Instr::TableGet(_, _)
+            | Instr::TableIndexMask(_)
+            | Instr::HashMask
+            | Instr::BinOp(_, _, _) => 1,
+        })
+        .sum()
+}
+
+// The largest bound on the sum in the TAC that keeps hashing a key of the
+// given length within max_instrs, if the TAC has a sum and the bound would
+// read fewer symbols of that key than it does.
+pub fn sum_bound_within(tac: &Tac, key_len: usize, max_instrs: u64) -> Option<u32> {
+    let len = to_u64(key_len);
+    let reads = tac.instrs().iter().find_map(|instr| match *instr {
+        Instr::StrSum(_) => Some(len),
+        Instr::StrSumBounded { head, tail, .. } => Some(len.min(u64::from(head) + u64::from(tail))),
+        _ => None,
+    })?;
+    let rest = worst_case_instrs(tac, key_len) - reads * SUM_INSTRS_PER_SYMBOL;
+    // The head and the tail read the same number of symbols.
+    let bound = max_instrs.checked_sub(rest)? / SUM_INSTRS_PER_SYMBOL / 2;
+    (bound > 0 && bound * 2 < reads).then(|| to_u32(bound))
+}
+
+impl Cost {
+    pub fn new(tac: &Tac, tables: &Tables, hash_table_len: usize, bounds: InputBounds) -> Cost {
+        let tac = Optimizer::new(tables, hash_table_len, bounds).optimize(tac);
+        let tables = narrow_tables(&tac, tables);
+
+        let mut cost = Cost {
+            instrs: 0,
+            loads: 0,
+            loops: 0,
+            table_bytes: 0,
+            slots: hash_table_len,
+            cases: 0,
+            branches: 0,
+        };
+        let mut used_tables = HashSet::new();
+        for instr in tac.instrs() {
+            match *instr {
+                Instr::Imm(_) => continue,
+                Instr::StrGet(_) => cost.loads += 1,
+                Instr::StrSum(_) | Instr::StrHash(_) => {
+                    cost.loads += 1;
+                    cost.loops += 1;
+                }
+                // Unrolled, so a load and an add for each symbol.
+                Instr::StrSumBounded { head, tail, .. } => {
+                    cost.loads += to_usize(head.saturating_add(tail));
+                    cost.instrs += to_usize(head.saturating_add(tail));
+                }
+                Instr::TableGet(t, _) => {
+                    cost.loads += 1;
+                    used_tables.insert(t);
+                }
+                Instr::StrLen
+                | Instr::TableIndexMask(_)
+                | Instr::HashMask
+                | Instr::BinOp(_, _, _) => (),
+            }
+            cost.instrs += 1;
+        }
+        for t in used_tables {
+            let table = &tables[t];
+            let max = table.iter().copied().max().unwrap_or(0);
+            let element_bytes = bit_width(max).div_ceil(8).next_power_of_two();
+            cost.table_bytes += table.len() * element_bytes as usize;
+        }
+        cost
+    }
+
+    pub fn of_tree(tree: &DecisionTree) -> Cost {
+        let mut cost = Cost {
+            instrs: 0,
+            loads: 0,
+            loops: 0,
+            table_bytes: 0,
+            slots: 0,
+            cases: 0,
+            branches: 0,
+        };
+        cost.add_tree(tree, 0, 0);
+        // Only the deepest key is counted, like the TAC of a hash function.
+        cost.instrs = cost.branches;
+        cost
+    }
+
+    fn add_tree(&mut self, tree: &DecisionTree, branches: usize, loads: usize) {
+        match *tree {
+            DecisionTree::Leaf(_) => {
+                self.branches = self.branches.max(branches);
+                self.loads = self.loads.max(loads);
+            }
+            DecisionTree::Switch(ref expr, ref arms) => {
+                let loads = loads + usize::from(*expr != Expr::StrLen);
+                self.cases += arms.len();
+                for (_, arm) in arms {
+                    self.add_tree(arm, branches + 1, loads);
+                }
+            }
+        }
+    }
+
+    pub fn score(&self, model: CostModel) -> u64 {
+        let instrs = to_u64(self.instrs);
+        let loads = to_u64(self.loads);
+        let loops = to_u64(self.loops);
+        let table_bytes = to_u64(self.table_bytes);
+        let slot_bytes = to_u64(self.slots) * SLOT_BYTES;
+        let cases = to_u64(self.cases);
+        let branches = to_u64(self.branches);
+        match model {
+            // Count instructions and case labels as a few bytes of code each.
+            CostModel::Smallest => {
+                table_bytes + slot_bytes + 4 * (instrs + loops * LOOP_INSTRS + cases)
+            }
+            // Count loads as a few instructions each, and a little for the
+            // cache pressure of the data.
+            CostModel::Fastest => {
+                instrs
+                    + 4 * loads
+                    + loops * LOOP_INSTRS
+                    + branches * BRANCH_INSTRS
+                    + (table_bytes + slot_bytes) / 256
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::ExprBuilder;
+
+    const BOUNDS: InputBounds = InputBounds {
+        max_key_len: 8,
+        max_symbol: 255,
+    };
+
+    #[test]
+    fn test_cost() {
+        let x = ExprBuilder();
+        let mut tables = Tables::new();
+        // Only the low 4 bits of each element reach the hash.
+        let t = tables.push(vec![0, 300, 2, 3]);
+
+        let mut tac = Tac::new();
+        let mix = tac.push_expr(x.add(x.str_get(x.imm(0)), x.str_len()));
+        tac.push_expr(x.and(
+            x.add(
+                x.reg(mix),
+                x.table_get(t, x.and(x.reg(mix), x.table_index_mask(t))),
+            ),
+            x.hash_mask(),
+        ));
+
+        let cost = Cost::new(&tac, &tables, 16, BOUNDS);
+        assert_eq!(
+            cost,
+            Cost {
+                instrs: 7,
+                loads: 2,
+                loops: 0,
+                table_bytes: 4,
+                slots: 16,
+                cases: 0,
+                branches: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_of_tree() {
+        let x = ExprBuilder();
+        let leaf = |key: &str| DecisionTree::Leaf(key.bytes().map(u32::from).collect());
+        let tree = DecisionTree::Switch(
+            x.str_len(),
+            vec![
+                (2, leaf("if")),
+                (
+                    4,
+                    DecisionTree::Switch(
+                        x.str_get(x.imm(0)),
+                        vec![
+                            (u32::from(b'e'), leaf("else")),
+                            (u32::from(b'l'), leaf("loop")),
+                        ],
+                    ),
+                ),
+            ],
+        );
+        assert_eq!(
+            Cost::of_tree(&tree),
+            Cost {
+                instrs: 2,
+                loads: 1,
+                loops: 0,
+                table_bytes: 0,
+                slots: 0,
+                cases: 4,
+                branches: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_worst_case_instrs() {
+        let x = ExprBuilder();
+        let mut tac = Tac::new();
+        tac.push_expr(x.and(
+            x.add(x.str_sum(0), x.str_sum_bounded(0, 1, 2)),
+            x.hash_mask(),
+        ));
+        // The add, the and and the mask, plus three for each symbol summed.
+        assert_eq!(worst_case_instrs(&tac, 0), 3);
+        assert_eq!(worst_case_instrs(&tac, 2), 3 + 2 * 3 + 2 * 3);
+        assert_eq!(worst_case_instrs(&tac, 10), 3 + 10 * 3 + 3 * 3);
+    }
+
+    #[test]
+    fn test_sum_bound_within() {
+        let x = ExprBuilder();
+        let mut tac = Tac::new();
+        tac.push_expr(x.and(x.add(x.str_sum(0), x.str_len()), x.hash_mask()));
+        // Four instructions besides the sum, which reads all ten symbols.
+        assert_eq!(worst_case_instrs(&tac, 10), 4 + 10 * 3);
+        assert_eq!(sum_bound_within(&tac, 10, 34), None);
+        assert_eq!(sum_bound_within(&tac, 10, 33), Some(4));
+        assert_eq!(sum_bound_within(&tac, 10, 16), Some(2));
+        assert_eq!(sum_bound_within(&tac, 10, 9), None);
+
+        let mut tac = Tac::new();
+        tac.push_expr(x.and(x.str_sum_bounded(0, 2, 2), x.hash_mask()));
+        assert_eq!(sum_bound_within(&tac, 10, 14), None);
+        assert_eq!(sum_bound_within(&tac, 10, 13), Some(1));
+    }
+
+    #[test]
+    fn test_score() {
+        let x = ExprBuilder();
+        let tables = Tables::new();
+
+        let mut sum = Tac::new();
+        sum.push_expr(x.and(x.str_sum(0), x.hash_mask()));
+        let mut get = Tac::new();
+        get.push_expr(x.and(x.str_get(x.imm(0)), x.hash_mask()));
+
+        let sum_cost = Cost::new(&sum, &tables, 16, BOUNDS);
+        let get_cost = Cost::new(&get, &tables, 256, BOUNDS);
+        assert!(get_cost.score(CostModel::Fastest) < sum_cost.score(CostModel::Fastest));
+        assert!(sum_cost.score(CostModel::Smallest) < get_cost.score(CostModel::Smallest));
+    }
+}
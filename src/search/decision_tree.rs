@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use crate::{ir::Expr, spec::Spec};
+
+use super::selector::Selector;
+
+// Every key gets its own branch in the emitted code, so trees are only tried
+// for sets this small.
+pub const MAX_TREE_KEYS: usize = 32;
+
+// Finds a key by branching on one symbol at a time, for sets so small that a
+// few branches are cheaper than a hash table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecisionTree {
+    // The only interpreted key that can reach this point.
+    Leaf(Vec<u32>),
+    // Branches on the value of an expression that reads at most one symbol.
+    // The arms are in increasing order of value.
+    Switch(Expr, Vec<(u32, DecisionTree)>),
+}
+
+impl DecisionTree {
+    // Builds a tree from selectors that distinguish the keys, or returns None
+    // if any of them reads more than one symbol.
+    pub fn new(spec: &Spec, sels: &[Selector]) -> Option<DecisionTree> {
+        let keys = &spec.interpreted_keys;
+        if keys.len() > MAX_TREE_KEYS {
+            return None;
+        }
+        let exprs: Vec<Expr> = sels
+            .iter()
+            .map(Selector::symbol_expr)
+            .collect::<Option<_>>()?;
+        let cols: Vec<Vec<u32>> = sels.iter().map(|sel| sel.eval(keys)).collect();
+        let rows: Vec<usize> = (0..keys.len()).collect();
+        Some(Self::build(keys, &exprs, &cols, &rows))
+    }
+
+    fn build(keys: &[Vec<u32>], exprs: &[Expr], cols: &[Vec<u32>], rows: &[usize]) -> DecisionTree {
+        if let [row] = *rows {
+            return DecisionTree::Leaf(keys[row].clone());
+        }
+
+        // Branch on whichever selector splits the keys into the most groups,
+        // preferring earlier selectors on ties.
+        let mut best: Option<(usize, BTreeMap<u32, Vec<usize>>)> = None;
+        for (c, col) in cols.iter().enumerate() {
+            let mut groups: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+            for &row in rows {
+                groups.entry(col[row]).or_default().push(row);
+            }
+            if best
+                .as_ref()
+                .is_none_or(|(_, best)| groups.len() > best.len())
+            {
+                best = Some((c, groups));
+            }
+        }
+        let (c, groups) = best.unwrap();
+        // The selectors distinguish the keys, so some selector splits them.
+        assert!(groups.len() > 1);
+
+        let arms = groups
+            .into_iter()
+            .map(|(value, rows)| (value, Self::build(keys, exprs, cols, &rows)))
+            .collect();
+        DecisionTree::Switch(exprs[c].clone(), arms)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::ExprBuilder;
+
+    fn spec(strs: &[&str]) -> Spec {
+        Spec::new(
+            strs.iter()
+                .map(|s| s.bytes().map(u32::from).collect())
+                .collect(),
+        )
+    }
+
+    fn leaf(s: &str) -> DecisionTree {
+        DecisionTree::Leaf(s.bytes().map(u32::from).collect())
+    }
+
+    #[test]
+    fn test_new() {
+        let x = ExprBuilder();
+        let days = spec(&["mon", "tue", "thu"]);
+        let tree = DecisionTree::new(&days, &[Selector::Index(0), Selector::Index(1)]);
+        // The second symbol splits all three keys at once.
+        assert_eq!(
+            tree,
+            Some(DecisionTree::Switch(
+                x.str_get(x.imm(1)),
+                vec![
+                    (u32::from(b'h'), leaf("thu")),
+                    (u32::from(b'o'), leaf("mon")),
+                    (u32::from(b'u'), leaf("tue")),
+                ]
+            ))
+        );
+
+        // Both symbols split the keys in two, so the first one is used.
+        let pairs = spec(&["ab", "ac", "bb"]);
+        let tree = DecisionTree::new(&pairs, &[Selector::Index(0), Selector::Index(1)]);
+        assert_eq!(
+            tree,
+            Some(DecisionTree::Switch(
+                x.str_get(x.imm(0)),
+                vec![
+                    (
+                        u32::from(b'a'),
+                        DecisionTree::Switch(
+                            x.str_get(x.imm(1)),
+                            vec![(u32::from(b'b'), leaf("ab")), (u32::from(b'c'), leaf("ac"))]
+                        )
+                    ),
+                    (u32::from(b'b'), leaf("bb")),
+                ]
+            ))
+        );
+
+        assert_eq!(DecisionTree::new(&days, &[Selector::StrSum(0)]), None);
+    }
+}
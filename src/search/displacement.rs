@@ -0,0 +1,198 @@
+use std::cmp;
+
+use crate::{
+    ir::{str_hash, ExprBuilder, Reg, Tables, Tac},
+    util::{table_index_mask, table_size, to_u32, to_usize},
+};
+
+use super::rng::Rng;
+
+// Keys hashed into each bucket on average. Fewer keys per bucket make pilots
+// easier to find, at the cost of a larger pilot table.
+const KEYS_PER_BUCKET: usize = 4;
+
+// Each pilot holds a shift in its low bits and a displacement above them.
+const SHIFT_BITS: u32 = 5;
+
+// How many pairs of seeds to try before giving up. Needing more than one is
+// rare, and usually means two keys hash alike under both seeds.
+const MAX_SEED_ATTEMPTS: u32 = 16;
+
+// Places keys by hashing all of them, for sets too large for the selector
+// search. One hash picks a bucket, and the bucket's pilot picks which bits of
+// another hash to use and how far to displace them:
+//
+//     pilot = pilots[hash(bucket_seed) & bucket_mask]
+//     slot = ((hash(slot_seed) >> (pilot & 31)) + (pilot >> 5)) & hash_mask
+pub struct Displacement {
+    bucket_seed: u32,
+    slot_seed: u32,
+    pilots: Vec<u32>,
+}
+
+impl Displacement {
+    // With an rng, the seeds are drawn from it instead of counting up.
+    pub fn search(
+        keys: &[Vec<u32>],
+        hash_bits: u32,
+        mut rng: Option<&mut Rng>,
+    ) -> Option<Displacement> {
+        // Displacements must fit above the shift.
+        if hash_bits > 32 - SHIFT_BITS {
+            return None;
+        }
+        let mut bucket_bits = 0;
+        while table_size(bucket_bits) * KEYS_PER_BUCKET < keys.len() {
+            bucket_bits += 1;
+        }
+        for attempt in 0..MAX_SEED_ATTEMPTS {
+            let (bucket_seed, slot_seed) = match rng.as_deref_mut() {
+                Some(rng) => (rng.next_u32(), rng.next_u32()),
+                None => (2 * attempt, 2 * attempt + 1),
+            };
+            let buckets = Self::group_keys(keys, bucket_bits, bucket_seed, slot_seed);
+            if let Some(pilots) = Self::find_pilots(&buckets, hash_bits) {
+                return Some(Displacement {
+                    bucket_seed,
+                    slot_seed,
+                    pilots,
+                });
+            }
+            eprintln!("no pilots found with seeds {bucket_seed} and {slot_seed}");
+        }
+        None
+    }
+
+    // Returns the slot hash of every key, grouped by bucket.
+    fn group_keys(
+        keys: &[Vec<u32>],
+        bucket_bits: u32,
+        bucket_seed: u32,
+        slot_seed: u32,
+    ) -> Vec<Vec<u32>> {
+        let bucket_mask = table_index_mask(bucket_bits);
+        let mut buckets = vec![Vec::new(); table_size(bucket_bits)];
+        for key in keys {
+            let bucket = str_hash(key, bucket_seed) & bucket_mask;
+            buckets[to_usize(bucket)].push(str_hash(key, slot_seed));
+        }
+        buckets
+    }
+
+    // Buckets are placed largest first, while the hash table still has room
+    // for them. Slot 0 is left free for inputs that are not keys.
+    fn find_pilots(buckets: &[Vec<u32>], hash_bits: u32) -> Option<Vec<u32>> {
+        let hash_table_size = table_size(hash_bits);
+        let hash_mask = table_index_mask(hash_bits);
+        let max_shift = cmp::min(32 - hash_bits, table_index_mask(SHIFT_BITS));
+
+        let mut order: Vec<usize> = (0..buckets.len())
+            .filter(|&b| !buckets[b].is_empty())
+            .collect();
+        order.sort_by_key(|&b| cmp::Reverse(buckets[b].len()));
+
+        let mut seen = vec![false; hash_table_size];
+        seen[0] = true;
+        let mut full_before = 1;
+        let mut pilots = vec![0; buckets.len()];
+        let mut slots = Vec::new();
+
+        for b in order {
+            let bucket = &buckets[b];
+            let pilot = if let [hash] = **bucket {
+                while seen[full_before] {
+                    full_before += 1;
+                }
+                let displacement = to_u32(full_before).wrapping_sub(hash) & hash_mask;
+                Some(displacement << SHIFT_BITS)
+            } else {
+                Self::find_pilot(bucket, &seen, hash_bits, max_shift, &mut slots)
+            };
+
+            let pilot = pilot?;
+            for &hash in bucket {
+                seen[to_usize(Self::slot(hash, pilot, hash_mask))] = true;
+            }
+            pilots[b] = pilot;
+        }
+        Some(pilots)
+    }
+
+    fn find_pilot(
+        bucket: &[u32],
+        seen: &[bool],
+        hash_bits: u32,
+        max_shift: u32,
+        slots: &mut Vec<u32>,
+    ) -> Option<u32> {
+        let hash_mask = table_index_mask(hash_bits);
+        for shift in 0..=max_shift {
+            // Keys whose shifted hashes agree can't be told apart by any
+            // displacement.
+            slots.clear();
+            slots.extend(bucket.iter().map(|&hash| (hash >> shift) & hash_mask));
+            slots.sort_unstable();
+            if slots.windows(2).any(|w| w[0] == w[1]) {
+                continue;
+            }
+
+            'displacement: for displacement in 0..to_u32(seen.len()) {
+                for &slot in slots.iter() {
+                    if seen[to_usize(slot.wrapping_add(displacement) & hash_mask)] {
+                        continue 'displacement;
+                    }
+                }
+                return Some(displacement << SHIFT_BITS | shift);
+            }
+        }
+        None
+    }
+
+    fn slot(hash: u32, pilot: u32, hash_mask: u32) -> u32 {
+        let shift = pilot & table_index_mask(SHIFT_BITS);
+        (hash >> shift).wrapping_add(pilot >> SHIFT_BITS) & hash_mask
+    }
+
+    pub fn compile(self, tac: &mut Tac, tables: &mut Tables) -> Reg {
+        let x = ExprBuilder();
+        let pilots = tables.push(self.pilots);
+        let pilot = tac.push_expr(x.table_get(
+            pilots,
+            x.and(x.str_hash(self.bucket_seed), x.table_index_mask(pilots)),
+        ));
+        tac.push_expr(x.add(
+            x.shrl(
+                x.str_hash(self.slot_seed),
+                x.and(x.reg(pilot), x.imm(table_index_mask(SHIFT_BITS))),
+            ),
+            x.shrl(x.reg(pilot), x.imm(SHIFT_BITS)),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::Trace;
+
+    #[test]
+    fn test_search() {
+        let keys: Vec<Vec<u32>> = (0..3000)
+            .map(|i: u32| i.to_string().bytes().map(u32::from).collect())
+            .collect();
+        let hash_bits = 12;
+        let displacement = Displacement::search(&keys, hash_bits, None).unwrap();
+
+        let mut tac = Tac::new();
+        let mut tables = Tables::new();
+        let reg = displacement.compile(&mut tac, &mut tables);
+        let trace = Trace::new(&keys, &tac, &tables, None);
+        let mut seen = vec![false; table_size(hash_bits)];
+        seen[0] = true;
+        for &hash in &trace[reg] {
+            let slot = to_usize(hash & table_index_mask(hash_bits));
+            assert!(!seen[slot]);
+            seen[slot] = true;
+        }
+    }
+}
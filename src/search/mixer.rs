@@ -1,6 +1,11 @@
 use std::{collections::HashSet, iter, mem};
 
-use crate::ir::{ExprBuilder, Reg, Tac};
+use crate::{
+    ir::{ExprBuilder, Reg, Tac},
+    util::{to_u32, to_usize},
+};
+
+use super::rng::Rng;
 
 pub struct Mixer {
     pub shifts: Vec<u32>,
@@ -9,7 +14,9 @@ pub struct Mixer {
 }
 
 impl Mixer {
-    pub fn search(cols: &[&[u32]]) -> Option<Mixer> {
+    // With an rng, the search for each shift starts at a random point and
+    // wraps around.
+    pub fn search(cols: &[&[u32]], mut rng: Option<&mut Rng>) -> Option<Mixer> {
         assert!(!cols.is_empty());
         let width = cols[0].len();
         let mut shifts = vec![0];
@@ -17,7 +24,12 @@ impl Mixer {
         let mut new_mixes = vec![0; width];
         let mut seen = HashSet::with_capacity(width);
         'col: for i in 1..cols.len() {
-            'shift: for shift in *shifts.last().unwrap()..32 {
+            let min_shift = *shifts.last().unwrap();
+            let start = match rng.as_deref_mut() {
+                Some(rng) => min_shift + to_u32(rng.below(to_usize(32 - min_shift))),
+                None => min_shift,
+            };
+            'shift: for shift in (start..32).chain(min_shift..start) {
                 seen.clear();
                 for (lane, mix) in mixes.iter().copied().enumerate() {
                     let new_mix = mix.wrapping_add(cols[i][lane] << shift);
@@ -1,17 +1,32 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    slice,
+};
 
 use crate::{
-    ir::{Tables, Tac, Trace},
+    ir::{narrow_tables, Optimizer, Tables, Tac, Trace},
     spec::Spec,
     util::{to_u32, to_usize},
 };
 
-use super::compressor_searcher::CompressorSearchSolution;
+use super::{
+    compressor_searcher::CompressorSearchSolution, cost::worst_case_instrs,
+    decision_tree::DecisionTree,
+};
 
+#[derive(Clone)]
 pub struct Phf {
+    // Optimized for the keys that the hash accepts, with the tables narrowed
+    // to the bits that it reads, so that backends emit them as they are.
     pub tac: Tac,
     pub tables: Tables,
     pub key_table: Vec<Vec<u32>>,
+    // The index in the input of the key in each slot, or None for the
+    // placeholders in unused slots.
+    pub ordinals: Vec<Option<usize>>,
+    // If set, lookups are cheaper by branching on a few symbols than by
+    // probing the key table.
+    pub tree: Option<DecisionTree>,
 }
 
 impl Phf {
@@ -22,6 +37,7 @@ impl Phf {
             tables,
             hash_bits,
         }: CompressorSearchSolution,
+        max_instrs: Option<u64>,
     ) -> Phf {
         let mut key_table = vec![Vec::new(); 1 << hash_bits];
 
@@ -54,16 +70,44 @@ impl Phf {
             key_table[0] = fake_key;
         }
 
+        let mut first_ordinals: HashMap<&[u32], usize> = HashMap::new();
+        for (i, key) in spec.keys.iter().enumerate() {
+            first_ordinals.entry(key).or_insert(i);
+        }
+        let ordinals = key_table
+            .iter()
+            .enumerate()
+            .map(|(slot, key)| {
+                let is_fake_key = (slot == 0) ^ key.is_empty();
+                (!is_fake_key).then(|| first_ordinals[key.as_slice()])
+            })
+            .collect();
+
+        let tac = Optimizer::new(&tables, key_table.len(), spec.input_bounds()).optimize(&tac);
+        let tables = narrow_tables(&tac, &tables);
+
         let phf = Phf {
             tac,
             tables,
             key_table,
+            ordinals,
+            tree: None,
         };
-        phf.validate(spec);
+        phf.validate(spec, max_instrs);
         phf
     }
 
-    fn validate(&self, spec: &Spec) {
+    // Checks that every key hashes to its own slot. The hash functions reject
+    // keys outside the interpreted lengths before any other work, so loops
+    // over the key never run longer than the longest key; with max_instrs,
+    // also checks that no such key takes more instructions than that.
+    fn validate(&self, spec: &Spec, max_instrs: Option<u64>) {
+        if let Some(max_instrs) = max_instrs {
+            for len in spec.min_interpreted_key_len..=spec.max_interpreted_key_len {
+                assert!(worst_case_instrs(&self.tac, len) <= max_instrs);
+            }
+        }
+
         let mut keys = Vec::new();
         for (i, key) in self.key_table.iter().enumerate() {
             let is_real_key = key.is_empty() == (i == 0);
@@ -74,7 +118,7 @@ impl Phf {
                     0
                 } else {
                     Trace::new(
-                        &[key.clone()],
+                        slice::from_ref(key),
                         &self.tac,
                         &self.tables,
                         Some(self.key_table.len()),
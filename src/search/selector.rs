@@ -2,11 +2,78 @@ use std::{collections::HashSet, iter};
 
 use crate::{
     combinatorics::{ChooseGen, LendingIterator},
-    ir::{ExprBuilder, Reg, Tables, Tac},
+    ir::{str_hash, str_sum_bounded, Expr, ExprBuilder, Reg, Tables, Tac},
     spec::Spec,
-    util::{to_u32, to_usize},
+    util::{to_u32, to_u64, to_usize},
 };
 
+use super::{mixer::Mixer, rng::Rng};
+
+// Which kinds of selectors the search may use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelectorFamilies {
+    pub len: bool,
+    pub index: bool,
+    // Positions relative to the length: Sub, And and Shrl.
+    pub arith: bool,
+    // Positions looked up by length in a table.
+    pub table: bool,
+    // Loops over the whole key.
+    pub sum: bool,
+    // A seeded hash of the whole key, only tried when nothing else
+    // distinguishes the keys.
+    pub hash: bool,
+}
+
+impl Default for SelectorFamilies {
+    fn default() -> Self {
+        SelectorFamilies {
+            len: true,
+            index: true,
+            arith: true,
+            table: true,
+            sum: true,
+            hash: true,
+        }
+    }
+}
+
+pub const MAX_SUM_MASKS: u32 = 32;
+
+// Bounded sums are unrolled, so longer bounds only bloat the generated code.
+pub const MAX_SUM_BOUND: u32 = 256;
+
+// Distinct keys collide under a given seed with a chance of about n^2 / 2^33,
+// so this many seeds are plenty for any set that fits in memory.
+const MAX_HASH_SEEDS: u32 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelectorLimits {
+    // The most positions that are combined in one set of selectors.
+    pub search_exponent: usize,
+    // How many positions past the common prefix, or before the common suffix,
+    // are searched.
+    pub pos_limit: usize,
+    // StrSum masks below this are tried.
+    pub sum_masks: u32,
+    // If set, StrSum selectors only sum this many symbols from each end of
+    // the key, so that hashing takes constant time.
+    pub sum_bound: Option<u32>,
+    pub families: SelectorFamilies,
+}
+
+impl Default for SelectorLimits {
+    fn default() -> Self {
+        SelectorLimits {
+            search_exponent: 3,
+            pos_limit: 64,
+            sum_masks: MAX_SUM_MASKS,
+            sum_bound: None,
+            families: SelectorFamilies::default(),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Selector {
     Len,
@@ -15,11 +82,14 @@ pub enum Selector {
     And(u32),
     Shrl(u32),
     StrSum(u32),
+    // A StrSum over the first and last few symbols.
+    StrSumBounded(u32, u32),
     Table(Vec<u32>),
+    StrHash(u32),
 }
 
 impl Selector {
-    fn eval(&self, keys: &[Vec<u32>]) -> Vec<u32> {
+    pub fn eval(&self, keys: &[Vec<u32>]) -> Vec<u32> {
         let mut buf = vec![0; keys.len()];
         match *self {
             Selector::Len => {
@@ -62,20 +132,59 @@ impl Selector {
                     buf[i] = sum;
                 }
             }
+            Selector::StrSumBounded(mask, n) => {
+                for (i, key) in keys.iter().enumerate() {
+                    buf[i] = str_sum_bounded(key, mask, n, n);
+                }
+            }
             Selector::Table(_) => panic!(),
+            Selector::StrHash(seed) => {
+                for (i, key) in keys.iter().enumerate() {
+                    buf[i] = str_hash(key, seed);
+                }
+            }
         }
         buf
     }
 
+    // How far into the key the selector reads, which is a proxy for how long
+    // it takes to evaluate.
+    fn reach(&self, key: &[u32]) -> usize {
+        match *self {
+            Selector::Len => 0,
+            Selector::Index(k) => to_usize(k) + 1,
+            Selector::Sub(k) => key.len() - to_usize(k) + 1,
+            Selector::And(k) => (key.len() & to_usize(k)) + 1,
+            Selector::Shrl(k) => (key.len() >> k) + 1,
+            Selector::StrSum(_)
+            | Selector::StrSumBounded(_, _)
+            | Selector::Table(_)
+            | Selector::StrHash(_) => key.len(),
+        }
+    }
+
+    // The expression computing the selector, if it reads at most one symbol.
+    pub fn symbol_expr(&self) -> Option<Expr> {
+        let x = ExprBuilder();
+        match *self {
+            Selector::Len => Some(x.str_len()),
+            Selector::Index(i) => Some(x.str_get(x.imm(i))),
+            Selector::Sub(k) => Some(x.str_get(x.sub(x.str_len(), x.imm(k)))),
+            Selector::And(k) => Some(x.str_get(x.and(x.str_len(), x.imm(k)))),
+            Selector::Shrl(k) => Some(x.str_get(x.shrl(x.str_len(), x.imm(k)))),
+            Selector::StrSum(_)
+            | Selector::StrSumBounded(_, _)
+            | Selector::Table(_)
+            | Selector::StrHash(_) => None,
+        }
+    }
+
     pub fn compile(self, tac: &mut Tac, tables: &mut Tables) -> Reg {
         let x = ExprBuilder();
         let expr = match self {
-            Selector::Len => x.str_len(),
-            Selector::Index(i) => x.str_get(x.imm(i)),
-            Selector::Sub(k) => x.str_get(x.sub(x.str_len(), x.imm(k))),
-            Selector::And(k) => x.str_get(x.and(x.str_len(), x.imm(k))),
-            Selector::Shrl(k) => x.str_get(x.shrl(x.str_len(), x.imm(k))),
             Selector::StrSum(k) => x.str_sum(k),
+            Selector::StrSumBounded(k, n) => x.str_sum_bounded(k, n, n),
+            Selector::StrHash(seed) => x.str_hash(seed),
             Selector::Table(t) => {
                 if t.iter().all(|&n| n == 0) {
                     return Selector::Index(0).compile(tac, tables);
@@ -84,24 +193,60 @@ impl Selector {
                     x.str_get(x.table_get(t, x.str_len()))
                 }
             }
+            sel => sel.symbol_expr().unwrap(),
         };
         tac.push_expr(expr)
     }
 
-    pub fn search(spec: &Spec) -> Option<Vec<Selector>> {
-        let pos_limit = 64;
+    // Find up to max_candidates sets of selectors that distinguish the keys,
+    // each using more selectors than the last. On failure, returns which
+    // limits may have prevented finding any. With an rng, combinations of
+    // selectors are tried in a random order. With prefer_narrow, the search
+    // goes on for a while past sets that mix into more than NARROW_MIX_BITS.
+    pub fn search(
+        spec: &Spec,
+        limits: &SelectorLimits,
+        max_candidates: usize,
+        prefer_narrow: bool,
+        rng: Option<&mut Rng>,
+    ) -> Result<Vec<Vec<Selector>>, String> {
+        let families = limits.families;
 
-        let mut searcher = SelectorSearcher::new(spec);
+        spec.check_unique()?;
+
+        // Symbols shared by every key cannot distinguish them, so positions are
+        // searched in a window past the common prefix, or before the common
+        // suffix when counting from the end.
+        let pos_limit = limits.pos_limit;
+        let prefix_len = spec.common_prefix_len;
+        let suffix_len = spec.common_suffix_len;
+        if prefix_len > 0 || suffix_len > 0 {
+            eprintln!("keys share a {prefix_len}-symbol prefix and a {suffix_len}-symbol suffix");
+        }
+
+        let mut searcher = SelectorSearcher::new(spec, prefer_narrow, rng);
 
         let mut len_sels = Vec::new();
         let len_not_constant = spec.min_interpreted_key_len < spec.max_interpreted_key_len;
-        if len_not_constant {
+        let use_len = len_not_constant && families.len;
+        // Table selectors are indexed by the length.
+        let use_tables = use_len && families.table;
+        if use_len {
             len_sels.push(searcher.add_selector(Selector::Len));
         }
 
+        let mut index_end = spec.max_interpreted_key_len;
+        if !len_not_constant {
+            index_end -= suffix_len;
+        }
+        let window_truncated = index_end > prefix_len.saturating_add(pos_limit);
+        index_end = usize::min(index_end, prefix_len.saturating_add(pos_limit));
+        if !families.index {
+            index_end = prefix_len;
+        }
         let mut safe_index_sels = Vec::new();
         let mut all_index_sels = Vec::new();
-        for k in 0..usize::min(spec.max_interpreted_key_len, pos_limit) {
+        for k in prefix_len..index_end {
             let sel = searcher.add_selector(Selector::Index(to_u32(k)));
             if k < spec.min_interpreted_key_len {
                 safe_index_sels.push(sel);
@@ -110,8 +255,12 @@ impl Selector {
         }
 
         let mut arith_sels = Vec::new();
-        if len_not_constant {
-            for k in 1..=usize::min(spec.min_interpreted_key_len, pos_limit) {
+        if len_not_constant && families.arith {
+            let sub_end = usize::min(
+                spec.min_interpreted_key_len,
+                suffix_len.saturating_add(pos_limit),
+            );
+            for k in suffix_len + 1..=sub_end {
                 arith_sels.push(searcher.add_selector(Selector::Sub(to_u32(k))));
             }
             for k in 1..usize::min(spec.min_interpreted_key_len, pos_limit) {
@@ -125,8 +274,8 @@ impl Selector {
             }
         }
 
-        let choices = 'choices: {
-            let search_exponent = 3;
+        let candidates = 'choices: {
+            let search_exponent = limits.search_exponent;
 
             let index_arith_sels: Vec<_> = safe_index_sels
                 .iter()
@@ -134,25 +283,35 @@ impl Selector {
                 .copied()
                 .collect();
 
-            for num_choices in 0..=search_exponent {
+            let mut candidates = Vec::new();
+            'num_choices: for num_choices in 0..=search_exponent {
+                if candidates.len() == max_candidates {
+                    break;
+                }
+
                 if let Some(choices) =
                     searcher.find_distinguishing(&[], &safe_index_sels, num_choices, None)
                 {
-                    break 'choices choices;
+                    candidates.push(choices);
+                    continue 'num_choices;
                 }
 
                 if len_not_constant {
                     if let Some(choices) =
                         searcher.find_distinguishing(&[], &index_arith_sels, num_choices, None)
                     {
-                        break 'choices choices;
+                        candidates.push(choices);
+                        continue 'num_choices;
                     }
+                }
 
+                if use_len {
                     if let Some(mut choices) =
                         searcher.find_distinguishing(&len_sels, &safe_index_sels, num_choices, None)
                     {
                         choices.rotate_right(1);
-                        break 'choices choices;
+                        candidates.push(choices);
+                        continue 'num_choices;
                     }
 
                     if let Some(mut choices) = searcher.find_distinguishing(
@@ -162,31 +321,46 @@ impl Selector {
                         None,
                     ) {
                         choices.rotate_right(1);
-                        break 'choices choices;
+                        candidates.push(choices);
+                        continue 'num_choices;
                     }
                 }
             }
+            if !candidates.is_empty() {
+                break 'choices candidates;
+            }
 
-            if len_not_constant {
+            if use_tables {
                 for num_choices in 1..=search_exponent {
                     if let Some(table_sels) =
                         searcher.table_search(&len_sels, &all_index_sels, num_choices)
                     {
                         let mut sels = vec![Selector::Len];
                         sels.extend(table_sels);
-                        return Some(sels);
+                        return Ok(vec![sels]);
                     }
                 }
             }
 
             let mut sum_sels = Vec::new();
-            for mask in 0..32 {
-                sum_sels.push(searcher.add_selector(Selector::StrSum(mask)));
+            if families.sum {
+                // Summing half the longest key from each end already covers
+                // every symbol, so a larger bound adds only dead code.
+                let sum_bound = limits
+                    .sum_bound
+                    .map(|n| n.min(to_u32(spec.max_interpreted_key_len.div_ceil(2))));
+                for mask in 0..limits.sum_masks {
+                    let sel = match sum_bound {
+                        Some(n) => Selector::StrSumBounded(mask, n),
+                        None => Selector::StrSum(mask),
+                    };
+                    sum_sels.push(searcher.add_selector(sel));
+                }
             }
 
             for &sum_sel in &sum_sels {
                 for num_choices in 0..=search_exponent {
-                    if len_not_constant {
+                    if use_tables {
                         if let Some(table_sels) = searcher.table_search(
                             &[sum_sel, len_sels[0]],
                             &all_index_sels,
@@ -195,31 +369,90 @@ impl Selector {
                             let mut sels = vec![Selector::Len];
                             sels.extend(table_sels);
                             sels.push(searcher.selectors[sum_sel].clone());
-                            return Some(sels);
+                            return Ok(vec![sels]);
                         }
                     } else if let Some(choices) = searcher.find_distinguishing(
                         &[sum_sel],
-                        &safe_index_sels,
+                        &index_arith_sels,
                         num_choices,
                         None,
                     ) {
-                        break 'choices choices;
+                        break 'choices vec![choices];
                     }
                 }
             }
 
-            return None;
+            if families.hash {
+                for seed in 0..MAX_HASH_SEEDS {
+                    let hash_sel = searcher.add_selector(Selector::StrHash(seed));
+                    if let Some(choices) = searcher.find_distinguishing(&[hash_sel], &[], 0, None) {
+                        // Only a fallback if cheaper selectors were allowed.
+                        if families.index
+                            || families.len
+                            || families.arith
+                            || families.table
+                            || families.sum
+                        {
+                            eprintln!(
+                                "warning: no cheaper selectors distinguish the keys, \
+                                 falling back to hashing the whole key"
+                            );
+                        }
+                        break 'choices vec![choices];
+                    }
+                }
+            }
+
+            let mut reasons = vec![format!(
+                "at most {search_exponent} positions are combined (search exponent)"
+            )];
+            if window_truncated {
+                reasons.push(format!(
+                    "only {pos_limit} positions past the common prefix are searched (position limit)"
+                ));
+            }
+            for (allowed, needed, family) in [
+                (families.index, true, "index"),
+                (families.len, len_not_constant, "len"),
+                (families.arith, len_not_constant, "arith"),
+                (families.table, len_not_constant, "table"),
+                (families.sum, true, "sum"),
+                (families.hash, true, "hash"),
+            ] {
+                if needed && !allowed {
+                    reasons.push(format!("{family} selectors are not allowed"));
+                }
+            }
+            if families.sum && limits.sum_masks < MAX_SUM_MASKS {
+                reasons.push(format!("only {} StrSum masks are tried", limits.sum_masks));
+            }
+            if let (true, Some(n)) = (families.sum, limits.sum_bound) {
+                reasons.push(format!("StrSum only covers {n} symbols at each end"));
+            }
+            return Err(format!(
+                "no selectors distinguish the keys: {}",
+                reasons.join("; ")
+            ));
         };
 
-        Some(
-            choices
-                .iter()
-                .map(|&choice| searcher.selectors[choice].clone())
-                .collect(),
-        )
+        Ok(candidates
+            .iter()
+            .map(|choices| {
+                choices
+                    .iter()
+                    .map(|&choice| searcher.selectors[choice].clone())
+                    .collect()
+            })
+            .collect())
     }
 }
 
+// Mixes of up to this many bits fit in 16-bit table elements.
+const NARROW_MIX_BITS: u32 = 16;
+
+// How many more combinations to try after finding one that is too wide.
+const NARROW_SEARCH_BUDGET: usize = 1 << 12;
+
 struct SelectorSearcher<'a> {
     spec: &'a Spec,
     keys: &'a [Vec<u32>],
@@ -227,10 +460,12 @@ struct SelectorSearcher<'a> {
     selectors: Vec<Selector>,
     cols: Vec<Vec<u32>>,
     seen: HashSet<Vec<u32>>,
+    prefer_narrow: bool,
+    rng: Option<&'a mut Rng>,
 }
 
-impl SelectorSearcher<'_> {
-    fn new(spec: &Spec) -> SelectorSearcher {
+impl<'a> SelectorSearcher<'a> {
+    fn new(spec: &'a Spec, prefer_narrow: bool, rng: Option<&'a mut Rng>) -> SelectorSearcher<'a> {
         let keys = &spec.interpreted_keys;
         SelectorSearcher {
             spec,
@@ -239,6 +474,8 @@ impl SelectorSearcher<'_> {
             selectors: Vec::new(),
             cols: Vec::new(),
             seen: HashSet::with_capacity(keys.len()),
+            prefer_narrow,
+            rng,
         }
     }
 
@@ -260,22 +497,81 @@ impl SelectorSearcher<'_> {
             return None;
         }
 
+        // When choosing selectors for all keys, keep looking for a little
+        // while in case there is a choice that mixes into fewer bits, or that
+        // reads less of the frequently looked up keys.
+        let all_keys = row_range.is_none();
+        let prefer_narrow = all_keys && self.prefer_narrow;
+        let weighted = all_keys && self.spec.interpreted_weights.is_some();
         let row_range = row_range.unwrap_or((0, self.keys.len()));
 
-        let mut choices: Vec<_> = iter::repeat(0)
-            .take(num_choices)
+        // The best choices so far, ranked by whether they are too wide and
+        // then by their weighted cost.
+        let mut best: Option<((bool, u64), Vec<usize>)> = None;
+        let mut budget = NARROW_SEARCH_BUDGET;
+
+        let mut choices: Vec<_> = iter::repeat_n(0, num_choices)
             .chain(already_chosen.iter().copied())
             .collect();
+        let mut choosable = choosable.to_vec();
+        if let Some(rng) = self.rng.as_deref_mut() {
+            rng.shuffle(&mut choosable);
+        }
         let mut choose_gen = ChooseGen::new(choosable.len(), num_choices);
         while let Some(choosable_indices) = choose_gen.next() {
+            if best.is_some() {
+                if budget == 0 {
+                    break;
+                }
+                budget -= 1;
+            }
             for (i, &choosable_index) in choosable_indices.iter().enumerate() {
                 choices[i] = choosable[choosable_index];
             }
-            if self.distinguishes(&choices, row_range) {
+            if !self.distinguishes(&choices, row_range) {
+                continue;
+            }
+            let cost = if weighted {
+                self.weighted_cost(&choices)
+            } else {
+                0
+            };
+            if matches!(best, Some(((false, best_cost), _)) if cost >= best_cost) {
+                continue;
+            }
+            let wide = prefer_narrow && self.mix_bits(&choices) > NARROW_MIX_BITS;
+            if !wide && !weighted {
                 return Some(choices);
             }
+            if best.as_ref().is_none_or(|(rank, _)| (wide, cost) < *rank) {
+                best = Some(((wide, cost), choices.clone()));
+            }
+        }
+        best.map(|(_, choices)| choices)
+    }
+
+    fn weighted_cost(&self, choices: &[usize]) -> u64 {
+        let weights = self.spec.interpreted_weights.as_ref().unwrap();
+        self.keys
+            .iter()
+            .zip(weights)
+            .map(|(key, &weight)| {
+                let reach = choices
+                    .iter()
+                    .map(|&choice| self.selectors[choice].reach(key))
+                    .max()
+                    .unwrap_or(0);
+                weight.saturating_mul(to_u64(reach))
+            })
+            .fold(0, u64::saturating_add)
+    }
+
+    fn mix_bits(&self, choices: &[usize]) -> u32 {
+        let cols: Vec<&[u32]> = choices.iter().map(|&c| self.cols[c].as_slice()).collect();
+        match Mixer::search(&cols, None) {
+            Some(mixer) => mixer.mix_bits,
+            None => 32,
         }
-        None
     }
 
     fn distinguishes(&mut self, choices: &[usize], row_range: (usize, usize)) -> bool {
@@ -299,10 +595,12 @@ impl SelectorSearcher<'_> {
         all_index_sels: &[usize],
         num_choices: usize,
     ) -> Option<Vec<Selector>> {
+        // all_index_sels are the indices from the common prefix onwards.
         let mut tables = vec![vec![0; self.spec.max_interpreted_key_len + 1]; num_choices];
         for (group_start, group_end) in self.len_groups.clone() {
             let key_len = self.keys[group_start].len();
-            let num_index_sels = usize::min(key_len, all_index_sels.len());
+            let num_index_sels =
+                usize::min(key_len - self.spec.common_prefix_len, all_index_sels.len());
             let actual_num_choices = usize::min(num_choices, num_index_sels);
             if let Some(choices) = self.find_distinguishing(
                 already_chosen,
@@ -336,3 +634,112 @@ impl SelectorSearcher<'_> {
         groups
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spec(strs: impl IntoIterator<Item = String>) -> Spec {
+        Spec::new(
+            strs.into_iter()
+                .map(|s| s.chars().map(|c| c as u32).collect())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_common_prefix_and_suffix() {
+        let prefix = "com.example.".repeat(8);
+        let names = ["x1", "y2", "xx3", "yy4", "xxx5", "yyy6"];
+        let spec = spec(names.map(|name| format!("{prefix}{name}.java")));
+        assert_eq!(spec.common_prefix_len, prefix.len());
+        assert_eq!(spec.common_suffix_len, ".java".len());
+
+        let sels = Selector::search(&spec, &SelectorLimits::default(), 1, false, None)
+            .unwrap()
+            .remove(0);
+        assert!(matches!(sels[..], [Selector::Sub(k)] if to_usize(k) == ".java".len() + 1));
+    }
+
+    #[test]
+    fn test_index_past_common_prefix() {
+        let prefix = "/api/v1/".repeat(10);
+        let spec = spec(["users", "posts", "teams"].map(|name| format!("{prefix}{name}")));
+        let sels = Selector::search(&spec, &SelectorLimits::default(), 1, false, None)
+            .unwrap()
+            .remove(0);
+        assert!(matches!(sels[..], [Selector::Index(k)] if to_usize(k) == prefix.len()));
+
+        let unlimited = SelectorLimits {
+            pos_limit: usize::MAX,
+            ..SelectorLimits::default()
+        };
+        assert!(Selector::search(&spec, &unlimited, 1, false, None).is_ok());
+    }
+
+    #[test]
+    fn test_sum_bound_clamped_to_key_len() {
+        let spec = spec(["abcde", "bacde", "cabde"].map(String::from));
+        let limits = SelectorLimits {
+            sum_bound: Some(MAX_SUM_BOUND),
+            families: SelectorFamilies {
+                len: false,
+                index: false,
+                arith: false,
+                table: false,
+                sum: true,
+                hash: false,
+            },
+            ..SelectorLimits::default()
+        };
+        let sels = Selector::search(&spec, &limits, 1, false, None)
+            .unwrap()
+            .remove(0);
+        assert!(matches!(sels[..], [Selector::StrSumBounded(_, 3)]));
+    }
+
+    #[test]
+    fn test_report_limits() {
+        let two_keys = spec(["ab", "ba"].map(String::from));
+        let limits = SelectorLimits {
+            search_exponent: 1,
+            families: SelectorFamilies {
+                sum: false,
+                hash: false,
+                ..SelectorFamilies::default()
+            },
+            ..SelectorLimits::default()
+        };
+        assert!(Selector::search(&two_keys, &limits, 1, false, None).is_ok());
+
+        let three_keys = spec(["aab", "aba", "baa"].map(String::from));
+        assert_eq!(
+            Selector::search(&three_keys, &limits, 1, false, None).err(),
+            Some(
+                "no selectors distinguish the keys: at most 1 positions are combined \
+                 (search exponent); sum selectors are not allowed; hash selectors are not \
+                 allowed"
+                    .into()
+            )
+        );
+
+        // Hashing the whole key distinguishes any distinct keys.
+        let fallback = SelectorLimits {
+            families: SelectorFamilies {
+                hash: true,
+                ..limits.families
+            },
+            ..limits
+        };
+        let sels = Selector::search(&three_keys, &fallback, 1, false, None)
+            .unwrap()
+            .remove(0);
+        assert!(matches!(sels[..], [Selector::StrHash(_)]));
+
+        let duplicated = spec(["ab", "ba", "ab"].map(String::from));
+        assert_eq!(
+            Selector::search(&duplicated, &fallback, 1, false, None).err(),
+            Some("the key \"ab\" appears more than once".into())
+        );
+    }
+}
@@ -1,24 +1,46 @@
+use crate::ir::InputBounds;
+
+#[derive(Clone)]
 pub struct Spec {
     pub keys: Vec<Vec<u32>>,
     pub interpreted_keys: Vec<Vec<u32>>,
+    // How often each interpreted key is looked up, if known.
+    pub interpreted_weights: Option<Vec<u64>>,
     pub min_interpreted_key_len: usize,
     pub max_interpreted_key_len: usize,
+    // How many leading and trailing symbols every interpreted key shares. They
+    // never overlap, so their sum is at most min_interpreted_key_len.
+    pub common_prefix_len: usize,
+    pub common_suffix_len: usize,
     pub min_hash_bits: u32,
 }
 
 impl Spec {
     pub fn new(keys: Vec<Vec<u32>>) -> Spec {
-        let mut interpreted_keys = Vec::new();
-        for key in &keys {
+        Self::build(keys, None)
+    }
+
+    pub fn with_weights(keys: Vec<Vec<u32>>, weights: Vec<u64>) -> Spec {
+        assert_eq!(keys.len(), weights.len());
+        Self::build(keys, Some(weights))
+    }
+
+    fn build(keys: Vec<Vec<u32>>, weights: Option<Vec<u64>>) -> Spec {
+        let mut weighted_keys = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
             if !key.is_empty() {
-                interpreted_keys.push(key.clone());
+                let weight = weights.as_ref().map_or(0, |weights| weights[i]);
+                weighted_keys.push((key.clone(), weight));
             }
         }
-        if interpreted_keys.is_empty() {
-            interpreted_keys.push(vec!['!' as u32]);
+        if weighted_keys.is_empty() {
+            weighted_keys.push((vec!['!' as u32], 0));
         }
-        interpreted_keys.sort();
-        interpreted_keys.sort_by_key(Vec::len);
+        weighted_keys.sort();
+        weighted_keys.sort_by_key(|(key, _)| key.len());
+        let (interpreted_keys, interpreted_weights): (Vec<_>, Vec<_>) =
+            weighted_keys.into_iter().unzip();
+        let interpreted_weights = weights.map(|_| interpreted_weights);
 
         let min_hash_table_size = interpreted_keys.len() + 1;
         let mut min_hash_bits = 1;
@@ -29,12 +51,45 @@ impl Spec {
         let min_interpreted_key_len = interpreted_keys.iter().map(Vec::len).min().unwrap();
         let max_interpreted_key_len = interpreted_keys.iter().map(Vec::len).max().unwrap();
 
+        let first = &interpreted_keys[0];
+        let common_prefix_len = (0..min_interpreted_key_len)
+            .take_while(|&i| interpreted_keys.iter().all(|key| key[i] == first[i]))
+            .count();
+        let common_suffix_len = (1..=min_interpreted_key_len - common_prefix_len)
+            .take_while(|&k| {
+                let c = first[first.len() - k];
+                interpreted_keys.iter().all(|key| key[key.len() - k] == c)
+            })
+            .count();
+
         Spec {
             keys,
             interpreted_keys,
+            interpreted_weights,
             min_interpreted_key_len,
             max_interpreted_key_len,
+            common_prefix_len,
+            common_suffix_len,
             min_hash_bits,
         }
     }
+
+    // Keys are sorted, so any duplicates are next to each other.
+    pub fn check_unique(&self) -> Result<(), String> {
+        let keys = &self.interpreted_keys;
+        match keys.windows(2).find(|pair| pair[0] == pair[1]) {
+            Some(pair) => {
+                let key: String = pair[0].iter().filter_map(|&c| char::from_u32(c)).collect();
+                Err(format!("the key {key:?} appears more than once"))
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn input_bounds(&self) -> InputBounds {
+        InputBounds {
+            max_key_len: self.max_interpreted_key_len,
+            max_symbol: u8::MAX.into(),
+        }
+    }
 }
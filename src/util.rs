@@ -14,6 +14,14 @@ where
     x.try_into().unwrap()
 }
 
+pub fn to_u64<T>(x: T) -> u64
+where
+    T: TryInto<u64>,
+    <T as std::convert::TryInto<u64>>::Error: std::fmt::Debug,
+{
+    x.try_into().unwrap()
+}
+
 pub fn table_index_mask(index_bits: u32) -> u32 {
     assert!(index_bits <= 32);
     ((1u64 << index_bits) - 1).try_into().unwrap()